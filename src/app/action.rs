@@ -1,16 +1,16 @@
-use std::{sync::{Mutex, Arc}, rc::Rc, cell::RefCell, collections::HashMap, io::Write};
+use std::{sync::{Mutex, Arc}, collections::HashMap, io::Write, fs::remove_file};
 
-use postgres::{Client, Row};
+use postgres::Row;
 use postgres_protocol::escape::escape_literal;
 use cast::{u8, u64};
 use urlencoding::decode;
 use tempfile::NamedTempFile;
-use chrono::Local;
+use chrono::{Local, Utc, Duration};
 use regex::Regex;
 use serde_json::{Value, Map, Number};
 use sha3::{Digest, Sha3_512};
 
-use crate::sys::go::{storage::Storage, i18n::LangItem};
+use crate::sys::go::{storage::Storage, db::DbLease, i18n::LangItem, fastcgi::Output, stream::Stream};
 
 pub const ON_YEAR: u32 = 31622400;
 pub const DEFAULT_LANG: u8 = 0;
@@ -37,12 +37,20 @@ pub enum Data {
 }
 
 // Loaded file
+// The temporary file is deleted with the struct on any path of the request, including the panic, the controller moves it to keep it
 pub struct WebFile {
   pub size: usize,                      // File size
   pub name: String,                     // File name
   pub tmp: String,                      // Absolute path to file location
 }
 
+impl Drop for WebFile {
+  // Delete the temporary file
+  fn drop(&mut self) {
+    remove_file(&self.tmp).unwrap_or_default();
+  }
+}
+
 // Redirect header (HTTP Location)
 pub struct Location {
   pub url: String,              // Url
@@ -60,7 +68,7 @@ pub struct Cookie {
 pub struct Action<'a> {
   pub salt: String,                         // Salt for password
 
-  db_sql: Rc<RefCell<Client>>,              // Postgresql connection
+  db_sql: DbLease,                          // Postgresql connection, it is taken from the pool for each query
   pub db_counts: u64,                       // Count selected data
   pub db_err: bool,                         // Error of sql query
   pub db_error: String,                     // Error text
@@ -81,10 +89,14 @@ pub struct Action<'a> {
   pub post: HashMap<String, String>,        // POST data
   pub file: HashMap<String, Vec<WebFile>>,  // FILE data
   pub cookie: HashMap<String, String>,      // Cookies
+  pub filter: Option<&'a [u8]>,             // Data of the FastCGI Filter (the file from the WEB server)
 
   pub set_cookie: Cookie,                   // Cookie
   location: Option<Location>,               // Redirect (HTTP Location)
   pub http_code: Option<u16>,               // Header code (HTTP code)
+  pub content_type: String,                 // Content-Type of the answer
+  pub headers: Vec<String>,                 // Addition headers. Example: Content-Disposition: attachment; filename="export.csv"
+  output: Arc<Mutex<Output<Stream>>>,       // Answer to the WEB server, which is written by parts
   pub css: Vec<String>,                     // Addition css script
   pub js: Vec<String>,                      // Addition js script
   pub lang_code: String,                    // Current language code
@@ -107,7 +119,7 @@ pub struct Action<'a> {
 impl<'a> Action<'a> {
   // Constructor
   pub fn new(
-    sql: Rc<RefCell<Client>>, 
+    sql: DbLease, 
     salt: String, 
     storage: Arc<Mutex<Storage>>, 
     param: &'a HashMap<String, String>, 
@@ -116,6 +128,7 @@ impl<'a> Action<'a> {
     i18n: &'a HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>>,
     langs: &'a Vec<LangItem>,
     tpls: &'a HashMap<String, HashMap<String, HashMap<String, String>>>,
+    output: Arc<Mutex<Output<Stream>>>,
   ) -> Action<'a>{
 
     // Request init
@@ -291,9 +304,13 @@ impl<'a> Action<'a> {
       post,
       cookie,
       file,
+      filter: None,
 
       // response
       http_code: None,
+      content_type: "text/html; charset=utf-8".to_owned(),
+      headers: Vec::new(),
+      output,
       set_cookie: Cookie { key: cook_key.to_owned(), value: session.clone(), time: ON_YEAR },
       location: None,
       css: Vec::with_capacity(16),
@@ -327,9 +344,9 @@ impl<'a> Action<'a> {
   // DB block
   // Execute query to database
   pub fn db_query(&mut self, sql: &str)->Vec<Row> {
-    let mut db = self.db_sql.borrow_mut();
+    let res = self.db_sql.query(sql);
     
-    match db.query(sql, &[]) {
+    match res {
       Ok(res) => {
         self.db_err = false;
         self.db_counts = u64(res.len());
@@ -339,12 +356,18 @@ impl<'a> Action<'a> {
       Err(e) => {
         self.db_err = true;
         self.db_counts = 0;
-        self.db_error = e.to_string();
+        self.error(&format!("{} in query: {}", e, sql));
+        self.db_error = e;
         return Vec::new();
       },
     };
   }
 
+  // The database was available at the last query
+  pub fn db_available(&self) -> bool {
+    self.db_sql.available
+  }
+
   // Escape text.
   pub fn db_escape(&self, text: &str)->String {
     escape_literal(&text)
@@ -441,11 +464,13 @@ impl<'a> Action<'a> {
       if let Ok(tmp) = NamedTempFile::new() {
         if let Ok((mut f, p)) = tmp.keep() {
           if let Some(path) = p.to_str() {
-            if let Ok(_) = f.write_all(data) {
-              if let None = file.get(&k) {
+            // The file is deleted with the struct, even if it isn't written
+            let web = WebFile { size: data.len(), name: n, tmp: path.to_owned()};
+            if f.write_all(data).is_ok() {
+              if file.get(&k).is_none() {
                 file.insert(k.clone(), Vec::with_capacity(16));
               }
-              file.get_mut(&k).unwrap().push(WebFile { size: data.len(), name: n, tmp: path.to_owned()});
+              file.get_mut(&k).unwrap().push(web);
             }
           }
         }
@@ -536,6 +561,72 @@ impl<'a> Action<'a> {
     s
   }
 
+  // Prepare the headers of the answer
+  // Without the length the answer is sent by parts
+  pub fn header(&self, len: Option<usize>) -> String {
+    let mut answer: Vec<String> = Vec::with_capacity(16);
+    answer.push("HTTP/1.1 ".to_owned());
+
+    if let Some(location) = self.redirect_get() {
+      if location.permanently {
+        answer.push(format!("{}\r\n", Action::http_code_get(301)));
+      } else {
+        answer.push(format!("{}\r\n", Action::http_code_get(302)));
+      }
+      answer.push(format!("{}\r\n", location.url));
+    } else if let Some(code) = self.http_code {
+      answer.push(format!("{}\r\n", Action::http_code_get(code)));
+    } else {
+      answer.push(format!("{}\r\n", Action::http_code_get(200)));
+    }
+    let time = Utc::now() + Duration::seconds(self.set_cookie.time.into());
+    let date: String = time.format("%a, %d-%b-%Y %H:%M:%S GMT").to_string();
+    answer.push(format!("Set-Cookie: {}={}; Expires={}; Max-Age={}; path=/; domain={}; Secure; SameSite=none\r\n", self.set_cookie.key, self.set_cookie.value, date, self.set_cookie.time, self.host));
+    answer.push("Connection: keep-alive\r\n".to_owned());
+    answer.push(format!("Content-Type: {}\r\n", self.content_type));
+    for header in &self.headers {
+      answer.push(format!("{}\r\n", header));
+    }
+    if let Some(len) = len {
+      answer.push(format!("Content-Length: {}\r\n", len));
+    }
+    answer.push("\r\n".to_owned());
+    answer.join("")
+  }
+
+  // Send a part of the answer to the WEB server at once
+  // The first call sends the headers, so the http code, the redirect and the headers must be set before.
+  // Returns false if the WEB server has closed the connection.
+  pub fn write(&mut self, data: &[u8]) -> bool {
+    let mut output = Mutex::lock(&self.output).unwrap();
+    if !output.sent && !output.stdout(self.header(None).as_bytes()) {
+      return false;
+    }
+    if data.is_empty() {
+      return true;
+    }
+    output.stdout(data)
+  }
+
+  // Send a warning to the error log of the WEB server
+  pub fn warn(&mut self, text: &str) -> bool {
+    self.log("warning", text)
+  }
+
+  // Send an error to the error log of the WEB server
+  pub fn error(&mut self, text: &str) -> bool {
+    self.log("error", text)
+  }
+
+  // Send a message with the current controller to the stderr stream
+  fn log(&mut self, level: &str, text: &str) -> bool {
+    let text = match self.current.last() {
+      Some((module, class)) => format!("{}: {}/{}: {}\n", level, module, class, text),
+      None => format!("{}: {}\n", level, text),
+    };
+    Mutex::lock(&self.output).unwrap().stderr(text.as_bytes())
+  }
+
   // Session block
   // Get user lang_id
   pub fn lang_id_get(&self) -> Option<u8> {
@@ -598,7 +689,8 @@ impl<'a> Action<'a> {
     self.session_id = session_id;
     self.user_id = user_id;
     self.role_id = role_id;
-    let json: Value = serde_json::from_str(&data).unwrap();
+    // The broken session data is replaced with the empty session
+    let json: Value = serde_json::from_str(&data).unwrap_or(Value::Null);
     if let Value::Object(obj) = json {
       for (key, val) in obj {
         self.session_data.insert(key, self.session_get_value(val));
@@ -751,7 +843,30 @@ impl<'a> Action<'a> {
     false
   }
 
-  
+  // Checking access to the requested route for the FastCGI Authorizer
+  // Returns HTTP code: 200 - access granted, 401 - the guest must log in, 403 - access denied
+  pub fn authorize(&mut self) -> u16 {
+    let (module, class, action) = match self.extract_route() {
+      Some((module, class, action, _, lang_id)) => {
+        self.set_lang_id(lang_id);
+        (module, class, action)
+      },
+      None => {
+        // The url is redirected, so it doesn't belong to any controller
+        self.location = None;
+        self.set_lang_id(None);
+        return 200;
+      },
+    };
+    if self.get_access(&module, &class, &action) {
+      return 200;
+    }
+    if self.user_id == 0 {
+      return 401;
+    }
+    403
+  }
+
   // Lang block
   // Get the correct default user language
   pub fn set_lang_id(&mut self, lang_id: Option<u8>) {
@@ -802,6 +917,23 @@ impl<'a> Action<'a> {
     return Answer::None;
   }
 
+  // Start CRM system for the FastCGI Filter
+  // Route example: /index/index/filter
+  pub fn filter(&mut self, route: &str) -> Answer {
+    let load: Vec<&str> = route.splitn(4, '/').collect();
+    if load.len() != 4 || !load[0].is_empty() {
+      return Answer::None;
+    }
+    let (module, class, action) = (load[1], load[2], load[3]);
+    self.set_lang_id(None);
+    if !self.get_access(module, class, action) {
+      return Answer::None;
+    }
+    let mut data: HashMap<String, Data> = HashMap::with_capacity(256);
+    // The filter controller is always internal
+    self.run(module, class, action, "", &mut data, true)
+  }
+
   // Encode routes
   fn extract_route(&mut self) -> Option<(String, String, String, String, Option<u8>)> {
 
@@ -1007,6 +1139,8 @@ impl<'a> Action<'a> {
         }
       }
     }
+    let text = format!("template \"{}\" not found", view);
+    self.warn(&text);
     Answer::None
   }
 
@@ -1032,6 +1166,8 @@ impl<'a> Action<'a> {
           "head" => super::index::index::App::head(self, params, data, internal),
           "foot" => super::index::index::App::foot(self, params, data, internal),
           "not_found" => super::index::index::App::not_found(self, params, data, internal),
+          "filter" => super::index::index::App::filter(self, params, data, internal),
+          "export" => super::index::index::App::export(self, params, data, internal),
           _ => Answer::None
         },
         "menu" => match action {
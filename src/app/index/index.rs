@@ -52,4 +52,64 @@ impl App {
     action.http_code = Some(404);
     action.out("not_found", data)
   }
+
+  // Filter of the static html pages from the WEB server. Inserts the header after the <body> tag
+  pub fn filter(action: &mut Action, _params: &str, data: &mut HashMap<String, Data>, internal: bool) -> Answer {
+    if !internal {
+      action.redirect_set("/index/index/not_found", true);
+    }
+    let page = match action.filter {
+      Some(page) => String::from_utf8_lossy(page).to_string(),
+      None => return Answer::None,
+    };
+    let start = match page.find("<body") {
+      Some(start) => match page[start..].find('>') {
+        Some(finish) => start + finish + 1,
+        None => return Answer::None,
+      },
+      None => return Answer::None,
+    };
+    data.insert("lang".to_owned(), Data::String(action.lang_code.to_owned()));
+    if let Answer::String(head) = action.load("index", "index", "head", "", data) {
+      return Answer::String(format!("{}{}{}", &page[..start], head, &page[start..]));
+    };
+    Answer::None
+  }
+
+  // Export of the languages to CSV. The rows are sent to the WEB server by parts while they are read
+  pub fn export(action: &mut Action, _params: &str, _data: &mut HashMap<String, Data>, internal: bool) -> Answer {
+    if internal {
+      return Answer::None;
+    }
+    let res = action.db_query("SELECT lang_id, lang, code, name, enable FROM lang ORDER BY sort");
+    if action.db_err {
+      action.http_code = Some(500);
+      return Answer::String("".to_owned());
+    }
+    action.content_type = "text/csv; charset=utf-8".to_owned();
+    action.headers.push("Content-Disposition: attachment; filename=\"lang.csv\"".to_owned());
+    let mut part = String::from("lang_id;lang;code;name;enable\n");
+    for row in res {
+      let lang_id: i64 = row.get(0);
+      let lang: String = row.get(1);
+      let code: String = row.get(2);
+      let name: String = row.get(3);
+      let enable: bool = row.get(4);
+      part.push_str(&format!("{};{};{};{};{}\n", lang_id, App::csv(&lang), App::csv(&code), App::csv(&name), enable));
+      if part.len() >= 8192 {
+        // The WEB server has closed the connection
+        if !action.write(part.as_bytes()) {
+          return Answer::None;
+        }
+        part.clear();
+      }
+    }
+    action.write(part.as_bytes());
+    Answer::None
+  }
+
+  // Value of the CSV field in quotes
+  fn csv(value: &str) -> String {
+    format!("\"{}\"", value.replace('"', "\"\""))
+  }
 }
@@ -8,11 +8,23 @@ mod sys {
     pub mod storage;
     pub mod i18n;
     pub mod queue;
+    pub mod idle;
+    pub mod db;
     pub mod template;
+    pub mod stream;
+    pub mod handover;
+    pub mod snapshot;
+    pub mod signal;
+    pub mod systemd;
   }
   pub mod log;
+  pub mod error;
   pub mod help;
+  pub mod check;
   pub mod init;
+  pub mod irc;
+  #[cfg(test)]
+  pub mod test;
 }
 mod app {
   pub mod admin {
@@ -33,7 +45,7 @@ mod app {
 
 use std::{env, sync::{Arc, RwLock}};
 
-use sys::{init::{Init, AppAction}, go::go::Go, log::LogApp, app::App, help::Help};
+use sys::{init::{Init, AppAction}, go::go::Go, log::LogApp, app::App, help::Help, check::Check, irc::IrcCommand};
 
 // Program entry point
 fn main() {
@@ -44,24 +56,46 @@ fn main() {
   
   log.set(init.id, init.dir.clone());
 
+  // Reading program parameters, the config file can be in another directory
+  if let Err(e) = init.args(env::args().skip(1)) { log.exit_err(&e); };
+  log.set(init.id, init.dir.clone());
+
+  // Check the config file, all problems are shown instead of the first one
+  if let AppAction::CheckConfig = init.app {
+    Check::run(&mut init);
+  }
+
   // Loading the configuration file
   if let Err(e) = init.load() { log.exit_err(&e);};
-
-  // Reading program parameters
-  if let Err(e) = init.args(&mut env::args()) { log.exit_err(&e); };
-  match init.app {
+  let res = match init.app {
     // Start the server in the background stream and exit
     AppAction::Start => App::start(&init, &log),
     // Start FastCGI and CRM server
     AppAction::Go => {
       let i = Arc::new(RwLock::new(init));
-      let l = Arc::new(RwLock::new(log));
-      Go::start(i, l);
+      let l = Arc::new(RwLock::new(log.clone()));
+      Go::start(i, l)
     },
     // Send an IRC "stop" signal and exit
-    AppAction::Stop => App::stop(&init, &log),
+    AppAction::Stop => App::stop(&init),
+    // Send an IRC "restart" signal and exit
+    AppAction::Restart => App::restart(&init),
+    // Send an IRC "reload" signal and exit
+    AppAction::Reload => App::reload(&init),
+    // Show the state of the running server
+    AppAction::Status => App::show(IrcCommand::Status, &init),
+    AppAction::Workers => App::show(IrcCommand::Workers, &init),
+    AppAction::Stats => App::show(IrcCommand::Stats, &init),
+    AppAction::Queue => App::show(IrcCommand::Queue, &init),
+    // Already checked
+    AppAction::CheckConfig => Ok(()),
     // Show help
-    AppAction::Help => Help::help(),
+    AppAction::Help => {
+      Help::help();
+      Ok(())
+    },
+  };
+  if let Err(e) = res {
+    log.exit_err(&e);
   }
 }
-
@@ -1,110 +1,121 @@
-use super::{init::Init, log::LogApp};
+use super::{init::Init, log::{LogApp, DAEMON}, error::TryteexError, irc::{Irc, IrcAnswer, IrcChallenge, IrcCommand, IrcRequest, IRC_VERSION, IRC_MAX_LEN}, go::stream::Stream};
 
-use std::{process::Command, net::TcpStream, time::Duration, io::{Write, Read}, str::from_utf8};
+use std::{process::{Command, Stdio}, time::Duration, io::ErrorKind};
+#[cfg(unix)]
+use std::{io::Error, os::unix::process::CommandExt};
 
 pub struct App {}
 
 impl App {
   
   // Set the signal
-  fn set_control(str: &str, param: &str, init: &Init, log: &LogApp) -> Option<Vec<u8>> {
+  fn set_control(command: IrcCommand, param: &str, init: &Init) -> Result<Option<String>, TryteexError> {
 
     // Connection to the server
-     match TcpStream::connect_timeout(&init.sys.irc, Duration::from_secs(1)) {
+     match Stream::connect_timeout(&init.sys.irc, Duration::from_secs(1)) {
       Ok(mut tcp) => {
-        // Format IRC request
-        let request = format!("{} {} {}", &init.id, &str, &param);
-        // Send the IRC request
-        match tcp.write_all(&request.trim().as_bytes()) {
-          Ok(()) => {
-            // Set reading timeout
-            if let Err(e) = tcp.set_read_timeout(Some(Duration::from_secs(30))) {
-              log.exit_err(&LogApp::get_error(250, &e.to_string()));
-            }
-            let mut buffer: Vec<u8> = Vec::new();
-            // Reading data
-            match tcp.read_to_end(&mut buffer) {
-              Ok(size) => match size {
-                0 => log.exit_err(&LogApp::get_error(251, "")),
-                _ => {
-                  // Data received
-                  let data = &buffer[..size];
-                  // Split data
-                  let start = match data[..].iter().position(|&r| r == b' ') {
-                    Some(start) => start,
-                    None => log.exit_err(&LogApp::get_error(252, "")),
-                  };
-                  // Get PID of the server
-                  let _irc_id = match from_utf8(&data[..start-1]) {
-                    Ok(irc_id) => match irc_id {
-                      "" => log.exit_err(&LogApp::get_error(254, "")),
-                      text => match text.parse::<u16>() {
-                        Ok(val) => match val {
-                          0 => log.exit_err(&LogApp::get_error(253, text)),
-                          id => id,
-                        },
-                        Err(_) => log.exit_err(&LogApp::get_error(255, text)),
-                      },
-                    },
-                    Err(e) => log.exit_err(&LogApp::get_error(256, &e.to_string())),
-                  };
-                  // Split data
-                  let finish = match data[start+1..].iter().position(|&r| r == b' ') {
-                    Some(finish) => start + finish + 1,
-                    None => log.exit_err(&LogApp::get_error(257, "")),
-                  };
-                  // Get and check sended the IRC command
-                  match from_utf8(&data[start+1..finish]) {
-                    Ok(com) => {
-                      if !str.eq(com) { log.exit_err(&LogApp::get_error(259, com)); }
-                    },
-                    Err(e) => log.exit_err(&LogApp::get_error(258, &e.to_string())),
-                  };
-                  // Get the answer
-                  match from_utf8(&data[finish+1..finish+4]) {
-                    Ok(ok) => match ok {
-                      "ok:" => {
-                        let res = data[finish+4..].to_vec();
-                        if res.len() == 0 { return None; }
-                        return Some(res);
-                      },
-                      _ => log.exit_err(&LogApp::get_error(262, ok)),
-                    },
-                    Err(e) => log.exit_err(&LogApp::get_error(261, &e.to_string())),
-                  };
-                },
-              },
-              Err(e) => log.exit_err(&LogApp::get_error(260, &e.to_string())),
-            };
+        // Set reading timeout
+        // The "stop" is answered after the requests in progress are finished, it takes up to "grace_period"
+        let timeout = Duration::from_secs(u64::from(init.sys.grace_period) + 30);
+        if let Err(e) = tcp.set_read_timeout(Some(timeout)) {
+          return Err(TryteexError::Io(250, "".to_owned(), e));
+        }
+        // Read the challenge
+        let challenge: IrcChallenge = match Irc::read(&mut tcp, IRC_MAX_LEN) {
+          Ok(challenge) => challenge,
+          Err(e) if e.kind() == ErrorKind::InvalidData => return Err(TryteexError::Io(274, "".to_owned(), e)),
+          Err(e) => return Err(TryteexError::Io(260, "".to_owned(), e)),
+        };
+        if challenge.version != IRC_VERSION {
+          return Err(TryteexError::Server(275, format!("{}, expected {}", challenge.version, IRC_VERSION)));
+        }
+        // Send the signed IRC request
+        let request = IrcRequest::new(init.id, command, param, &init.sys.irc_key, &challenge.nonce);
+        if let Err(e) = Irc::write(&mut tcp, &request) {
+          return Err(TryteexError::Io(263, "".to_owned(), e));
+        }
+        // Read the answer
+        let answer: IrcAnswer = match Irc::read(&mut tcp, IRC_MAX_LEN) {
+          Ok(answer) => answer,
+          Err(e) => match e.kind() {
+            ErrorKind::UnexpectedEof => return Err(TryteexError::Server(251, "".to_owned())),
+            ErrorKind::InvalidData => return Err(TryteexError::Io(276, "".to_owned(), e)),
+            _ => return Err(TryteexError::Io(260, "".to_owned(), e)),
           },
-          Err(e) => log.exit_err(&LogApp::get_error(263, &e.to_string())),
         };
+        if let Some(error) = answer.error {
+          return Err(TryteexError::Server(273, error.text));
+        }
+        if answer.command != Some(command) {
+          return Err(TryteexError::Server(277, format!("{:?}", answer.command)));
+        }
+        match answer.data.len() {
+          0 => Ok(None),
+          _ => Ok(Some(answer.data)),
+        }
       },
-      Err(e) => match e.kind() {
-        std::io::ErrorKind::PermissionDenied => log.exit_err(&LogApp::get_error(264, "")),
-        std::io::ErrorKind::ConnectionRefused => log.exit_err(&LogApp::get_error(265, "")),
-        std::io::ErrorKind::ConnectionReset => log.exit_err(&LogApp::get_error(266, "")),
-        std::io::ErrorKind::ConnectionAborted => log.exit_err(&LogApp::get_error(267, "")),
-        std::io::ErrorKind::NotConnected => log.exit_err(&LogApp::get_error(268, "")),
-        std::io::ErrorKind::AddrNotAvailable => log.exit_err(&LogApp::get_error(269, &init.sys.irc.to_string())),
-        std::io::ErrorKind::TimedOut => log.exit_err(&LogApp::get_error(270, &init.sys.irc.to_string())),
-        _ =>log.exit_err(&LogApp::get_error(271, &e.to_string())),
-      },
-    };
+      Err(e) => Err(match e.kind() {
+        ErrorKind::PermissionDenied => TryteexError::Server(264, "".to_owned()),
+        ErrorKind::ConnectionRefused | ErrorKind::NotFound => TryteexError::Server(265, "".to_owned()),
+        ErrorKind::ConnectionReset => TryteexError::Server(266, "".to_owned()),
+        ErrorKind::ConnectionAborted => TryteexError::Server(267, "".to_owned()),
+        ErrorKind::NotConnected => TryteexError::Server(268, "".to_owned()),
+        ErrorKind::AddrNotAvailable => TryteexError::Server(269, init.sys.irc.to_string()),
+        ErrorKind::TimedOut => TryteexError::Server(270, init.sys.irc.to_string()),
+        _ => TryteexError::Io(271, "".to_owned(), e),
+      }),
+    }
   }
 
-  // Start the server in the background stream and exit
-  pub fn start(init: &Init, log: &LogApp) {
+  // Start the server in the background as a daemon and exit
+  // The server is started in the current directory with the same options to find the same config file,
+  // it is detached from the terminal, stdout and stderr are written to the log file
+  pub fn start(init: &Init, log: &LogApp) -> Result<(), TryteexError> {
     let file = &init.exe;
-    match Command::new(file).arg("go").current_dir(&init.dir).spawn() {
-        Ok(_) => {},
-        Err(e) => log.exit_err(&LogApp::get_error(201, &e.to_string())),
-    };
+    let out = log.open().map_err(|e| TryteexError::Io(201, "".to_owned(), e))?;
+    let err = out.try_clone().map_err(|e| TryteexError::Io(201, "".to_owned(), e))?;
+    let mut command = Command::new(file);
+    command.arg("go").args(&init.flags).env(DAEMON, "1").stdin(Stdio::null()).stdout(out).stderr(err);
+    #[cfg(unix)]
+    // Safety: only setsid is called between fork and exec, it is async-signal-safe
+    unsafe {
+      command.pre_exec(|| {
+        if libc::setsid() == -1 {
+          return Err(Error::last_os_error());
+        }
+        Ok(())
+      });
+    }
+    match command.spawn() {
+      Ok(_) => Ok(()),
+      Err(e) => Err(TryteexError::Io(201, "".to_owned(), e)),
+    }
   }
 
   // Send an IRC "stop" signal and exit
-  pub fn stop(init: &Init, log: &LogApp) {
-    App::set_control("stop", "", init, log);
+  pub fn stop(init: &Init) -> Result<(), TryteexError> {
+    App::set_control(IrcCommand::Stop, "", init)?;
+    Ok(())
+  }
+
+  // Send an IRC "reload" signal and exit
+  pub fn reload(init: &Init) -> Result<(), TryteexError> {
+    App::set_control(IrcCommand::Reload, "", init)?;
+    Ok(())
+  }
+
+  // Send an IRC command and print the answer of the server
+  pub fn show(command: IrcCommand, init: &Init) -> Result<(), TryteexError> {
+    if let Some(res) = App::set_control(command, "", init)? {
+      print!("{}", res);
+    }
+    Ok(())
+  }
+
+  // Send an IRC "restart" signal and exit when the new server is started
+  pub fn restart(init: &Init) -> Result<(), TryteexError> {
+    App::set_control(IrcCommand::Restart, "", init)?;
+    Ok(())
   }
 
 }
\ No newline at end of file
@@ -0,0 +1,45 @@
+use std::{path::Path, process};
+
+use super::{init::Init, error::TryteexError, go::{db::Db, template::Template}};
+
+// Validation of the config file
+pub struct Check {}
+
+impl Check {
+  // Check the config file and the application directory, print all problems and exit
+  // The exit code is 1 if there is an error, the warnings don't change it
+  pub fn run(init: &mut Init) -> ! {
+    println!("Config file: {}", init.conf);
+    let (mut errors, warnings) = init.check();
+    Check::dir(&init.dir, &mut errors);
+    if init.check_db && errors.is_empty() {
+      let (conn, tz) = Db::params(init);
+      match Db::new(conn, tz).connect() {
+        Ok(()) => println!("Database: connected to {}:{}/{}", init.db.host, init.db.port, init.db.name),
+        Err(e) => errors.push(e.to_string()),
+      }
+    }
+    for text in warnings.iter().chain(errors.iter()) {
+      println!("{}", text);
+    }
+    println!("Errors: {}, warnings: {}", errors.len(), warnings.len());
+    process::exit(if errors.is_empty() { 0 } else { 1 })
+  }
+
+  // The application directory must contain the templates "module/class/view_*.html"
+  fn dir(dir: &str, errors: &mut Vec<String>) {
+    if !Path::new(dir).is_dir() {
+      errors.push(TryteexError::Config(143, dir.to_owned()).to_string());
+      return;
+    }
+    let mut tpl = Template::new();
+    if let Err(e) = tpl.load_templates(dir) {
+      errors.push(e.to_string());
+      return;
+    }
+    let count: usize = tpl.tpls.values().flat_map(|module| module.values()).map(|class| class.len()).sum();
+    if count == 0 {
+      errors.push(TryteexError::Config(145, dir.to_owned()).to_string());
+    }
+  }
+}
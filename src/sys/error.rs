@@ -0,0 +1,223 @@
+use std::{error::Error, fmt, io};
+
+// Error of the server
+// The code is stable, it is shown as "Error <code>: <description><text>" in the log and in the answers of IRC
+#[derive(Debug)]
+pub enum TryteexError {
+  Config(u32, String),                  // Wrong value in the config file, the environment or the command line
+  Io(u32, String, io::Error),           // Error of the operating system and what was done, the text can be empty
+  Db(u32, postgres::Error),             // Error of the database
+  Server(u32, String),                  // Other error of the server, the text has the details
+  Nested(u32, String, Box<TryteexError>), // Error caused by another one, the text can be empty
+}
+
+impl TryteexError {
+  // Stable code of the error
+  pub fn code(&self) -> u32 {
+    match self {
+      TryteexError::Config(code, _) => *code,
+      TryteexError::Io(code, _, _) => *code,
+      TryteexError::Db(code, _) => *code,
+      TryteexError::Server(code, _) => *code,
+      TryteexError::Nested(code, _, _) => *code,
+    }
+  }
+
+  // Wrap the error into the error with another code
+  pub fn wrap(self, code: u32, text: &str) -> TryteexError {
+    TryteexError::Nested(code, text.to_owned(), Box::new(self))
+  }
+
+  // Description of the error code
+  fn message(code: u32) -> &'static str {
+    match code {
+      // Log error
+      1 => ": Can't write log to file. System message: ",
+      2 => ": Can't open log file. System message: ",
+      
+      // Config file error
+      100 => ": Unknown error when opening config file: ",
+      101 => ": Value \"max_connection\" must be > 0 in config file",
+      102 => ": Unknown value \"max_connection=",
+      103 => ": Unknown value \"socket=",
+      104 => ": Value \"socket\" mustn't be empty in config file",
+      105 => ": Value \"irc\" must be > 0 in config file",
+      106 => ": Unknown value \"irc=",
+      107 => ": Value \"dir\" mustn't be empty in config file",
+      108 => ": Value length \"dir=",
+      109 => ": Value \"version\" mustn't be empty in config file",
+      110 => ": Value length \"version=",
+      111 => ": Value \"db_host\" mustn't be empty in config file",
+      112 => ": Value \"db_port\" mustn't be empty in config file",
+      113 => ": Value \"db_user\" mustn't be empty in config file",
+      114 => ": Value \"db_pwd\" mustn't be empty in config file",
+      115 => ": Value \"db_name\" mustn't be empty in config file",
+      116 => ": Value \"salt\" mustn't be empty in config file",
+      117 => ": Value \"keep_alive\" must be > 0 in config file",
+      118 => ": Unknown value \"keep_alive=",
+      119 => ": Value \"socket_mode\" must be an octal file mode (for example 660) in config file: ",
+      120 => ": Value \"min_workers\" must be > 0 in config file",
+      121 => ": Unknown value \"min_workers=",
+      122 => ": Value \"max_workers\" must be > 0 in config file",
+      123 => ": Unknown value \"max_workers=",
+      124 => ": Value \"min_workers\" must be <= \"max_workers\" in config file",
+      125 => ": Value \"idle_timeout\" must be > 0 in config file",
+      126 => ": Unknown value \"idle_timeout=",
+      127 => ": Value \"read_timeout\" must be > 0 in config file",
+      128 => ": Unknown value \"read_timeout=",
+      129 => ": Value \"handler_timeout\" must be > 0 in config file",
+      130 => ": Unknown value \"handler_timeout=",
+      131 => ": Value \"write_timeout\" must be > 0 in config file",
+      132 => ": Unknown value \"write_timeout=",
+      133 => ": Can't read file \"error_page\" from config file. Error text: ",
+      134 => ": Value \"db_pool_size\" must be > 0 in config file",
+      135 => ": Unknown value \"db_pool_size=",
+      136 => ": Unknown value \"grace_period=",
+      137 => ": Unknown value \"watch=",
+      138 => ": Value \"irc\" must be a port or unix:/path/to/socket in config file: ",
+      139 => ": Value \"irc_mode\" must be an octal file mode (for example 600) in config file: ",
+      140 => ": Unknown key in config file: ",
+      141 => ": Unrecognized line in config file: ",
+      142 => ": Sections aren't used in config file, the values are read as without them: ",
+      143 => ": Directory \"dir\" from config file isn't found: ",
+      144 => ": Value \"dir\" must end with \"/\" in config file: ",
+      145 => ": No templates \"module/class/view_*.html\" in \"dir\" from config file: ",
+      146 => ": Wrong value of the environment variable ",
+      147 => ": Wrong value of the option ",
+      148 => ": Value \"db_pool_wait\" must be > 0 in config file",
+      149 => ": Unknown value \"db_pool_wait=",
+      150 => ": Value \"db_pool_wait\" must be < \"handler_timeout\" in config file",
+      151 => ": Value \"db_pool_idle\" must be > 0 in config file",
+      152 => ": Unknown value \"db_pool_idle=",
+
+      // Action error
+      200 => ": Unknown command: ",
+      201 => ": Start server error: ",
+      202 => ": Can't write the pid file. Error text: ",
+      203 => ": Can't set the handling of the signals. Error text: ",
+      204 => ": Unknown option: ",
+      205 => ": The option needs a value: ",
+
+      // Command error
+      250 => ": Can't send command. System error: ",
+      251 => ": Read empty data from IRC channel.",
+      // 252-259, 261 and 262 are raised only by the previous versions of IRC protocol, the codes keep their meaning
+      252 => ": Unrecognized data read. Error: ",
+      253 => ": Unrecognized IRC answer. Data: ",
+      254 => ": Receive error data.",
+      255 => ": Unrecognized IRC answer. Data: ",
+      256 => ": Unrecognized IRC answer. Data: ",
+      257 => ": Unrecognized data read. Error: ",
+      258 => ": Unrecognized IRC answer. Data: ",
+      259 => ": Receive error data.",
+      260 => ": Can't read command from IRC channel. System error: ",
+      261 => ": Unrecognized IRC answer. Data: ",
+      262 => ": Receive error data.",
+      263 => ": Can't send command. System error: ",
+      264 => ": Permission denied to connect to IRC server",
+      265 => ": Connection refused of IRC server",
+      266 => ": Connection reset of IRC server",
+      267 => ": Connection aborted of IRC server",
+      268 => ": Not connected to IRC server",
+      269 => ": IP addr not available ",
+      270 => ": Connection timeout. Maybe server IRC not started ",
+      271 => ": Connection error: ",
+      272 => ": Send to stdout error data. Error: ",
+      273 => ": The server can't run the command. Error: ",
+      274 => ": Unrecognized IRC challenge. Error: ",
+      275 => ": The IRC protocol version of the server isn't supported: ",
+      276 => ": Unrecognized IRC answer. Error: ",
+      277 => ": The answer is for another IRC command: ",
+
+      // Start server
+      300 => ": Permission denied to open IRC socket",
+      301 => ": Socket busy for opening IRC socket",
+      302 => ": IRC socket not avaibale for opening",
+      303 => ": Error open IRC socket. System error: ", 
+      304 => ": IRC command with the wrong signature is rejected. Peer and command: ", 
+
+      // SQL error
+      350 => ": Error connect to sql server. Error text: ", 
+      351 => ": Error set time_zone. Error text: ", 
+      352 => ": The database isn't available, the connection will be restored later", 
+      353 => ": No free connection in the database pool", 
+
+      // Lang
+      370 => ": Error get langs. Error text: ", 
+
+      // Template
+      380 => ": Error get templates. Error text: ", 
+
+      // Start fastCGI server
+      400 => ": Permission denied to open socket",
+      401 => ": Socket busy for opening socket",
+      402 => ": Socket not avaibale for opening",
+      403 => ": Error open socket. System error: ", 
+      404 => ": Can't set the mode or the owner of the unix domain socket. System error: ", 
+      405 => ": Can't take the sockets from the previous server. Data: ", 
+      406 => ": Can't take the sockets passed by systemd. Data: ", 
+
+      // Server go
+      500 => ": The network connection is abruptly disconnected. System error: ",
+      501 => ": Mix up connections.",
+      502 => ": Queue is wrong.",
+      503 => ": Can't start a new worker. Error text: ",
+      504 => ": The request is out of \"handler_timeout\", the worker is recycled. Worker: ",
+      505 => ": The request is out of \"read_timeout\", the connection is closed. Worker: ",
+      506 => ": Panic in the controller, the answer is 500. Request: ",
+      507 => ": The grace period is over, the requests in progress are cut off. Workers: ",
+      508 => ": Can't restart the server. Error text: ",
+      509 => ": Can't reload templates and translations, the previous ones are kept. Error text: ",
+      510 => ": Can't reload the config file, the previous values are kept. Error text: ",
+      511 => ": Can't reopen the log file. Error text: ",
+
+      // Unknown error
+      _ => ": Unknown error: ",
+    }
+  }
+
+  // End of the description, which goes after the wrong value
+  fn tail(code: u32) -> &'static str {
+    match code {
+      102 | 103 | 106 | 118 | 121 | 123 | 126 | 128 | 130 | 132 | 135 | 136 | 137 | 149 | 152 => "\" in config file",
+      108 => "\" must be < 1024 in config file",
+      110 => "\" must be < 12 in config file",
+      _ => "",
+    }
+  }
+
+  // Description of the error with its details, without the code
+  fn detail(&self) -> String {
+    let text = match self {
+      TryteexError::Config(_, text) | TryteexError::Server(_, text) => text.clone(),
+      TryteexError::Io(_, text, e) if text.is_empty() => e.to_string(),
+      TryteexError::Io(_, text, e) => format!("{} {}", text, e),
+      TryteexError::Db(_, e) => e.to_string(),
+      TryteexError::Nested(_, text, e) if text.is_empty() => e.to_string(),
+      TryteexError::Nested(_, text, e) => format!("{}. {}", text, e),
+    };
+    format!("{}{}{}", TryteexError::message(self.code()), text, TryteexError::tail(self.code()))
+  }
+
+  // Show the problem as a warning, it doesn't stop the program. Example: the unknown key in the config file
+  pub fn warning(&self) -> String {
+    format!("Warning {}{}", self.code(), self.detail())
+  }
+}
+
+impl fmt::Display for TryteexError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "Error {}{}", self.code(), self.detail())
+  }
+}
+
+impl Error for TryteexError {
+  fn source(&self) -> Option<&(dyn Error + 'static)> {
+    match self {
+      TryteexError::Io(_, _, e) => Some(e),
+      TryteexError::Db(_, e) => Some(e),
+      TryteexError::Nested(_, _, e) => Some(e.as_ref()),
+      _ => None,
+    }
+  }
+}
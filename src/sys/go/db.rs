@@ -0,0 +1,283 @@
+use std::{time::{Duration, Instant}, sync::{Arc, Mutex, Condvar, PoisonError}};
+
+use postgres::{Client, NoTls, Row, CancelToken};
+use postgres_protocol::escape::escape_literal;
+
+use crate::sys::{init::Init, error::TryteexError};
+
+// First delay between attempts to reconnect
+const DELAY_MIN: Duration = Duration::from_millis(500);
+// Max delay between attempts to reconnect
+const DELAY_MAX: Duration = Duration::from_secs(30);
+
+// Connection of the worker to the database
+// The broken connection is restored at the next query, the delay between attempts grows twice up to DELAY_MAX
+pub struct Db {
+  client: Option<Client>,             // Postgresql connection, None - the database isn't available
+  conn: String,                       // Connection string
+  tz: String,                         // Query to set the timezone
+  next: Instant,                      // Time of the next attempt to reconnect
+  delay: Duration,                    // Delay before the next attempt to reconnect
+}
+
+impl Db {
+  // Constructor
+  pub fn new(conn: String, tz: String) -> Db {
+    Db {
+      client: None,
+      conn,
+      tz,
+      next: Instant::now(),
+      delay: DELAY_MIN,
+    }
+  }
+
+  // Connection string and the query to set the timezone from the config file
+  pub fn params(init: &Init) -> (String, String) {
+    let db = &init.db;
+    let tz = format!("SET timezone TO {};", escape_literal(&init.time_zone));
+    let conn = format!("host='{}' port='{}' dbname='{}' user='{}' password='{}' connect_timeout=2 application_name='{} {}' options='--client_encoding=UTF8'", db.host, &db.port, &db.name, &db.user, &db.pwd, &env!("CARGO_PKG_NAME"), &env!("CARGO_PKG_VERSION"));
+    (conn, tz)
+  }
+
+  // Connect to the database and set the timezone
+  pub fn connect(&mut self) -> Result<(), TryteexError> {
+    self.client = None;
+    let mut client = match Client::connect(&self.conn, NoTls) {
+      Ok(client) => client,
+      Err(e) => return Err(TryteexError::Db(350, e)),
+    };
+    if !self.tz.is_empty() {
+      if let Err(e) = client.batch_execute(&self.tz) {
+        return Err(TryteexError::Db(351, e));
+      }
+    }
+    self.client = Some(client);
+    self.delay = DELAY_MIN;
+    Ok(())
+  }
+
+  // Get the connection, reconnect if it is closed
+  // Returns None while the database isn't available
+  pub fn client(&mut self) -> Option<&mut Client> {
+    if let Some(client) = &self.client {
+      if !client.is_closed() {
+        return self.client.as_mut();
+      }
+    }
+    let now = Instant::now();
+    if now < self.next {
+      return None;
+    }
+    if self.connect().is_err() {
+      self.next = now + self.delay;
+      self.delay = (self.delay * 2).min(DELAY_MAX);
+      return None;
+    }
+    self.client.as_mut()
+  }
+
+  // Execute query
+  // The query is repeated once if the connection was broken before it, for example after the restart of the database
+  pub fn query(&mut self, sql: &str) -> Result<Vec<Row>, String> {
+    let client = match self.client() {
+      Some(client) => client,
+      None => return Err(TryteexError::Server(352, "".to_owned()).to_string()),
+    };
+    match client.query(sql, &[]) {
+      Ok(res) => Ok(res),
+      Err(e) if client.is_closed() && e.is_closed() => match self.client() {
+        Some(client) => client.query(sql, &[]).map_err(|e| e.to_string()),
+        None => Err(TryteexError::Server(352, "".to_owned()).to_string()),
+      },
+      Err(e) => Err(e.to_string()),
+    }
+  }
+}
+
+// Pool of the connections to the database, which is shared by all workers
+// The connection is created at the first demand and closed after "db_pool_idle" without work
+pub struct DbPool {
+  list: Mutex<PoolList>,              // Free connections
+  cond: Condvar,                      // Signal about a returned connection
+  size: usize,                        // Max number of connections
+  wait: Duration,                     // Max time to wait for a free connection
+  idle: Duration,                     // Idle time after which the free connection is closed
+  conn: String,                       // Connection string
+  tz: String,                         // Query to set the timezone
+}
+
+// Free connections of the pool
+struct PoolList {
+  free: Vec<(Db, Instant)>,           // Connections and time when they were returned
+  count: usize,                       // Number of the created connections
+}
+
+impl DbPool {
+  // Constructor
+  pub fn new(size: usize, wait: Duration, idle: Duration, conn: String, tz: String) -> DbPool {
+    DbPool {
+      list: Mutex::new(PoolList {
+        free: Vec::with_capacity(size),
+        count: 0,
+      }),
+      cond: Condvar::new(),
+      size,
+      wait,
+      idle,
+      conn,
+      tz,
+    }
+  }
+
+  // Check the connection to the database at the start
+  pub fn check(&self) -> Result<(), TryteexError> {
+    let mut db = match self.take() {
+      Some(db) => db,
+      None => return Err(TryteexError::Server(353, "".to_owned())),
+    };
+    let res = db.connect();
+    self.put(db);
+    res
+  }
+
+  // Take the connection, waiting while all connections are busy
+  // Returns None if no connection becomes free in time
+  fn take(&self) -> Option<Db> {
+    let start = Instant::now();
+    let mut list = Mutex::lock(&self.list).unwrap();
+    loop {
+      self.close_idle(&mut list);
+      if let Some((db, _)) = list.free.pop() {
+        return Some(db);
+      }
+      if list.count < self.size {
+        list.count += 1;
+        return Some(Db::new(self.conn.clone(), self.tz.clone()));
+      }
+      let elapsed = start.elapsed();
+      if elapsed >= self.wait {
+        return None;
+      }
+      list = self.cond.wait_timeout(list, self.wait - elapsed).unwrap().0;
+    }
+  }
+
+  // Return the connection to the pool
+  fn put(&self, db: Db) {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    list.free.push((db, Instant::now()));
+    self.close_idle(&mut list);
+    self.cond.notify_one();
+  }
+
+  // Close the connections which have been free longer than "db_pool_idle"
+  fn close_idle(&self, list: &mut PoolList) {
+    let len = list.free.len();
+    let idle = self.idle;
+    list.free.retain(|(_, time)| time.elapsed() < idle);
+    list.count -= len - list.free.len();
+  }
+}
+
+// Connection of one request, it is taken from the pool for each query and returned after it
+// The connection is kept from "BEGIN" to "COMMIT" or "ROLLBACK", the controller without the database doesn't hold it
+pub struct DbLease {
+  pool: Arc<DbPool>,                          // Pool
+  db: Option<Db>,                             // Connection, None - isn't taken
+  cancel: Arc<Mutex<Option<CancelToken>>>,    // Token to cancel the running query for the watchdog
+  transaction: bool,                          // The transaction is open, the connection is kept
+  pub available: bool,                        // The database was available at the last query
+}
+
+impl DbLease {
+  // Constructor
+  pub fn new(pool: Arc<DbPool>, cancel: Arc<Mutex<Option<CancelToken>>>) -> DbLease {
+    DbLease {
+      pool,
+      db: None,
+      cancel,
+      transaction: false,
+      available: true,
+    }
+  }
+
+  // Execute query
+  pub fn query(&mut self, sql: &str) -> Result<Vec<Row>, String> {
+    if self.db.is_none() {
+      self.db = self.pool.take();
+    }
+    let db = match self.db.as_mut() {
+      Some(db) => db,
+      None => {
+        self.available = false;
+        return Err(TryteexError::Server(353, "".to_owned()).to_string());
+      },
+    };
+    let token = db.client().map(|client| client.cancel_token());
+    self.available = token.is_some();
+    *Mutex::lock(&self.cancel).unwrap() = token;
+    let res = db.query(sql);
+    if let Some(transaction) = DbLease::transaction(sql) {
+      // The failed "BEGIN" doesn't open the transaction, the failed "COMMIT" closes it anyway
+      self.transaction = transaction && res.is_ok();
+    }
+    if !self.transaction {
+      self.release();
+    }
+    res
+  }
+
+  // Change of the transaction by the query: Some(true) - it is opened, Some(false) - it is closed
+  fn transaction(sql: &str) -> Option<bool> {
+    let sql = sql.trim_start().to_lowercase();
+    let mut words = sql.split(|c: char| !c.is_ascii_alphabetic()).filter(|word| !word.is_empty());
+    match (words.next(), words.next()) {
+      (Some("begin"), _) | (Some("start"), Some("transaction")) => Some(true),
+      // "ROLLBACK TO SAVEPOINT" keeps the transaction
+      (Some("rollback"), Some("to")) => None,
+      (Some("commit"), _) | (Some("end"), _) | (Some("rollback"), _) | (Some("abort"), _) => Some(false),
+      _ => None,
+    }
+  }
+
+  // Return the connection to the pool
+  fn release(&mut self) {
+    *Mutex::lock(&self.cancel).unwrap() = None;
+    if let Some(db) = self.db.take() {
+      self.pool.put(db);
+    }
+  }
+}
+
+impl Drop for DbLease {
+  // Return the connection to the pool
+  fn drop(&mut self) {
+    *Mutex::lock(&self.cancel).unwrap_or_else(PoisonError::into_inner) = None;
+    if let Some(mut db) = self.db.take() {
+      // The controller panicked or forgot "COMMIT", the transaction is left open
+      db.query("ROLLBACK").unwrap_or_default();
+      self.pool.put(db);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::DbLease;
+
+  #[test]
+  fn transaction_is_found_by_query() {
+    assert_eq!(DbLease::transaction("BEGIN"), Some(true));
+    assert_eq!(DbLease::transaction("\n  begin isolation level serializable;"), Some(true));
+    assert_eq!(DbLease::transaction("START TRANSACTION READ ONLY"), Some(true));
+    assert_eq!(DbLease::transaction("COMMIT"), Some(false));
+    assert_eq!(DbLease::transaction("end"), Some(false));
+    assert_eq!(DbLease::transaction("ROLLBACK;"), Some(false));
+    assert_eq!(DbLease::transaction("abort"), Some(false));
+    assert_eq!(DbLease::transaction("ROLLBACK TO SAVEPOINT a"), None);
+    assert_eq!(DbLease::transaction("SAVEPOINT a"), None);
+    assert_eq!(DbLease::transaction("SELECT 'begin'"), None);
+    assert_eq!(DbLease::transaction("WITH a AS (SELECT 1) SELECT * FROM a"), None);
+  }
+}
@@ -1,7 +1,7 @@
-use std::{net::TcpStream, time::Duration, thread, io::{ErrorKind, Read, Write, Error}, collections::HashMap, rc::Rc};
+use std::{io::{ErrorKind, Read, Write, Error}, collections::HashMap, sync::{Arc, Mutex}};
 
 use byteorder::{BigEndian, ByteOrder};
-use cast::{u8, u16, u32, usize};
+use cast::{u8, u16, u32};
 
 // FastCGI header
 pub struct Header
@@ -15,7 +15,7 @@ pub struct Header
 }
 
 // FastCGI header type
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub enum HeaderType {
   BeginRequest,
   AbortRequest,
@@ -32,6 +32,7 @@ pub enum HeaderType {
 }
 
 // FastCGI role
+#[derive(Clone, Copy)]
 pub enum Role {
   Responder,
   Authorized,
@@ -56,7 +57,6 @@ pub enum ContentData {
   ErrorStream,
   Break,
   Unknown(Unknown),
-  Raw(Rc<Vec<u8>>, usize, u16),
   End(End),
 }
 
@@ -81,6 +81,7 @@ pub enum RecordType {
   Some(Record),
   ErrorStream,
   StreamClosed,
+  Timeout,
 }
 
 // FastCGI Unknown request data
@@ -96,8 +97,6 @@ pub struct End {
   pub reserved: [u8; 3],
 }
 
-pub const MS1: std::time::Duration = Duration::from_millis(1);
-
 pub const FASTCGI_VERSION: u8 = 1;
 
 pub const FASTCGI_HEADER_LEN: usize = 8;
@@ -122,14 +121,102 @@ pub const FASTCGI_RESPONDER: u16 = 1;
 pub const FASTCGI_AUTHORIZER: u16 = 2;
 pub const FASTCGI_FILTER: u16 = 3;
 
+pub const FASTCGI_KEEP_CONN: u8 = 1;
+
 pub const FASTCGI_REQUEST_COMPLETE: u8 = 0;
 
+// Answer of one request to the WEB server, which is written by parts
+pub struct Output<W: Write> {
+  writer: Arc<Mutex<W>>,          // Shared writing half of the connection
+  request_id: u16,                // Request ID
+  pub sent: bool,                 // Part of the answer has already been sent
+  pub error: bool,                // Something has been sent to the stderr stream
+  pub broken: bool,               // The WEB server closed the connection
+  pub finished: bool,             // END_REQUEST has been sent, the request ID may be used again by the WEB server
+}
+
+impl<W: Write> Output<W> {
+  // Constructor
+  pub fn new(writer: Arc<Mutex<W>>, request_id: u16) -> Output<W> {
+    Output {
+      writer,
+      request_id,
+      sent: false,
+      error: false,
+      broken: false,
+      finished: false,
+    }
+  }
+
+  // Send a part of the answer at once
+  pub fn stdout(&mut self, data: &[u8]) -> bool {
+    if self.broken {
+      return false;
+    }
+    self.sent = true;
+    let mut writer = Mutex::lock(&self.writer).unwrap();
+    if FastCGI::write_stdout(self.request_id, data, &mut *writer).is_err() {
+      self.broken = true;
+    }
+    !self.broken
+  }
+
+  // Send a diagnostic message to the error log of the WEB server
+  pub fn stderr(&mut self, text: &[u8]) -> bool {
+    if self.broken {
+      return false;
+    }
+    self.error = true;
+    let mut writer = Mutex::lock(&self.writer).unwrap();
+    if FastCGI::write_stderr(self.request_id, text, &mut *writer).is_err() {
+      self.broken = true;
+    }
+    !self.broken
+  }
+
+  // Finish the answer, which isn't ready in time
+  // If the headers have already been sent, the answer is cut off
+  pub fn timeout(&mut self, page: &[u8]) {
+    if self.broken || self.finished {
+      return;
+    }
+    self.broken = true;
+    self.finished = true;
+    let mut writer = Mutex::lock(&self.writer).unwrap();
+    if !self.sent && FastCGI::write_stdout(self.request_id, page, &mut *writer).is_err() {
+      return;
+    }
+    FastCGI::write_end(self.request_id, self.error, &mut *writer).unwrap_or(());
+  }
+
+  // Finish the answer
+  pub fn end(&mut self) -> bool {
+    if self.broken || self.finished {
+      return false;
+    }
+    self.finished = true;
+    let mut writer = Mutex::lock(&self.writer).unwrap();
+    if FastCGI::write_end(self.request_id, self.error, &mut *writer).is_err() {
+      self.broken = true;
+    }
+    !self.broken
+  }
+}
+
 // Wrapper of FastCGI server
 pub struct FastCGI { }
 
 impl FastCGI {
+  // Get the role of the request from the "Begin" record
+  pub fn role(record: &Record) -> Role {
+    match &record.data {
+      ContentData::BeginRequest(begin) => begin.role,
+      _ => Role::Responder,
+    }
+  }
+
   // Read FastCGI records
-  pub fn read_record(seek: &mut usize, size: &mut usize, need_read: &mut bool, buffer: &mut[u8], stream: &mut TcpStream, max_connection: usize) -> RecordType {
+  pub fn read_record<S: Read, W: Write>(seek: &mut usize, size: &mut usize, need_read: &mut bool, buffer: &mut[u8], stream: &mut S, writer: &Mutex<W>, max_connection: usize) -> RecordType {
     loop{
       if *need_read {
         // Checks indicator to read from the stream buffer
@@ -153,7 +240,7 @@ impl FastCGI {
             *size += n;
             *need_read = false;
             // Read one record
-            match FastCGI::read(seek, size, buffer, stream, max_connection) {
+            match FastCGI::read(seek, size, buffer, writer, max_connection) {
               ReadStatus::Continue => {
                 *need_read = true;
                 continue;
@@ -169,16 +256,13 @@ impl FastCGI {
               *need_read = true;
               continue;
             },
-            ErrorKind::WouldBlock => {
-              thread::sleep(MS1);
-              continue;
-            },
+            ErrorKind::WouldBlock | ErrorKind::TimedOut => return RecordType::Timeout,
             ErrorKind::ConnectionReset => return RecordType::StreamClosed,
             _ => break,
           },
         };
       } else {
-        match FastCGI::read(seek, size, buffer, stream, max_connection) {
+        match FastCGI::read(seek, size, buffer, writer, max_connection) {
           ReadStatus::Continue => {
             *need_read = true;
             continue;
@@ -194,7 +278,7 @@ impl FastCGI {
   }
 
   // Decode one FastCGI record
-  fn read(seek: &mut usize, size: &mut usize, buffer: &mut[u8], stream: &mut TcpStream, max_connection: usize) -> ReadStatus {
+  fn read<W: Write>(seek: &mut usize, size: &mut usize, buffer: &mut[u8], writer: &Mutex<W>, max_connection: usize) -> ReadStatus {
     if *size - *seek < FASTCGI_HEADER_LEN {
       return ReadStatus::Continue;
     }
@@ -222,7 +306,7 @@ impl FastCGI {
       if let HeaderType::GetValues | HeaderType::BeginRequest = header.header_type {
         return ReadStatus::Break;
       }
-      if let HeaderType::Params | HeaderType::AbortRequest | HeaderType::Stdin = header.header_type {
+      if let HeaderType::Params | HeaderType::AbortRequest | HeaderType::Stdin | HeaderType::Data = header.header_type {
         let record = Record {
           header,
           data: ContentData::None,
@@ -231,7 +315,7 @@ impl FastCGI {
       }
     }
     // Check buffer size
-    if *size - *seek < usize::from(header.content_length) + usize::from(header.padding_length) {
+    if *size - *seek < usize::from(header.content_length) + usize::from(header.padding_length) + FASTCGI_HEADER_LEN {
       return ReadStatus::Continue;
     }
     let hseek = *seek + FASTCGI_HEADER_LEN;
@@ -245,11 +329,11 @@ impl FastCGI {
         FastCGI::read_begin_request(&buffer[hseek..hseek + usize::from(header.content_length)])
       },
       HeaderType::Params => FastCGI::read_param(&mut buffer[hseek..hseek + usize::from(header.content_length)]),
-      HeaderType::GetValues => FastCGI::read_write_value(header.request_id, stream, max_connection),
+      HeaderType::GetValues => FastCGI::read_write_value(header.request_id, writer, max_connection),
       HeaderType::AbortRequest => FastCGI::read_stream(&mut buffer[hseek..hseek + usize::from(header.content_length)]),
       HeaderType::Stdin => FastCGI::read_stream(&mut buffer[hseek..hseek + usize::from(header.content_length)]),
       HeaderType::Data => FastCGI::read_stream(&mut buffer[hseek..hseek + usize::from(header.content_length)]),
-      HeaderType::Error(unknown) => FastCGI::write_unknown(unknown, header.request_id, stream),
+      HeaderType::Error(unknown) => FastCGI::write_unknown(unknown, header.request_id, writer),
       _ => ContentData::Error,
     };
     *seek += usize::from(header.content_length) + usize::from(header.padding_length) + FASTCGI_HEADER_LEN;
@@ -270,28 +354,28 @@ impl FastCGI {
   }
 
   // Answer GetValues low-level request
-  fn read_write_value(request_id: u16, stream: &mut TcpStream, max_connection: usize) -> ContentData {
+  fn read_write_value<W: Write>(request_id: u16, writer: &Mutex<W>, max_connection: usize) -> ContentData {
     if request_id > 0 {
       return ContentData::None;
     }
     let mut params: HashMap<String, String> = HashMap::with_capacity(3);
     params.insert("FCGI_MAX_CONNS".to_owned(), max_connection.to_string());
-    params.insert("FASTCGI_MAX_REQS".to_owned(), max_connection.to_string());
-    params.insert("FASTCGI_MAX_CONNS".to_owned(), max_connection.to_string());
+    params.insert("FCGI_MAX_REQS".to_owned(), max_connection.to_string());
+    params.insert("FCGI_MPXS_CONNS".to_owned(), "1".to_owned());
 
-    let len = u16(params.len()).unwrap();
     let record = Record {
       header: Header {
         version: FASTCGI_VERSION,
         header_type: HeaderType::GetValuesResult,
         request_id,
-        content_length: len,
+        content_length: 0,
         padding_length: 0,
         reserved: 0,
       },
       data: ContentData::Param(params),
     };
     let data = FastCGI::record_array(record);
+    let mut stream = Mutex::lock(writer).unwrap();
     if let Err(_) = stream.write_all(&data[..]) {
       return ContentData::ErrorStream;
     }
@@ -299,7 +383,7 @@ impl FastCGI {
   }
 
   // Answer unknown command
-  fn write_unknown(unknown: u8, request_id: u16, stream: &mut TcpStream) -> ContentData {
+  fn write_unknown<W: Write>(unknown: u8, request_id: u16, writer: &Mutex<W>) -> ContentData {
     let record = Record {
       header: Header {
         version: FASTCGI_VERSION,
@@ -316,6 +400,7 @@ impl FastCGI {
     };
 
     let data = FastCGI::record_array(record);
+    let mut stream = Mutex::lock(writer).unwrap();
     if let Err(_) = stream.write_all(&data[..]) {
       return ContentData::ErrorStream;
     }
@@ -461,39 +546,61 @@ impl FastCGI {
     }
   }
 
-  // Answer to the WEB server
-  pub fn write_response(header: &Header, answer: Vec<u8>, stream: &mut TcpStream) -> Result<(), Error> {
-    let mut data: Vec<u8> = Vec::new();
-    let len = answer.len();
-    let mut size: u16;
-    let mut seek: usize = 0;
-    let pack = Rc::new(answer);
+  // Write a part of the answer to the WEB server
+  pub fn write_stdout<W: Write>(request_id: u16, answer: &[u8], stream: &mut W) -> Result<(), Error> {
+    FastCGI::write_stream(HeaderType::Stdout, request_id, answer, stream)
+  }
+
+  // Write a diagnostic message to the error log of the WEB server
+  pub fn write_stderr<W: Write>(request_id: u16, text: &[u8], stream: &mut W) -> Result<(), Error> {
+    FastCGI::write_stream(HeaderType::Stderr, request_id, text, stream)
+  }
+
+  // Write data to the output stream
+  fn write_stream<W: Write>(header_type: HeaderType, request_id: u16, answer: &[u8], stream: &mut W) -> Result<(), Error> {
     // Split data to parts
-    while seek < len {
-      if seek + FASTCGI_MAX_CONTENT_LEN < len {
-        size = u16(FASTCGI_MAX_CONTENT_LEN).unwrap();
-      } else {
-        size = u16(len - seek).unwrap();
+    for part in answer.chunks(FASTCGI_MAX_CONTENT_LEN) {
+      let record = Record {
+        header: Header {
+          version: FASTCGI_VERSION,
+          header_type,
+          request_id,
+          content_length: u16(part.len()).unwrap(),
+          padding_length: 0,
+          reserved: 0,
+        },
+        data: ContentData::None,
       };
+      let mut data = FastCGI::record_array(record);
+      data.extend_from_slice(part);
+      stream.write_all(&data[..])?;
+    }
+    Ok(())
+  }
+
+  // Finish the answer to the WEB server
+  // The stderr stream is closed only if something was written to it
+  pub fn write_end<W: Write>(request_id: u16, stderr: bool, stream: &mut W) -> Result<(), Error> {
+    let mut data = Vec::with_capacity(3 * FASTCGI_HEADER_LEN + 8);
+    if stderr {
       let record = Record {
         header: Header {
           version: FASTCGI_VERSION,
-          header_type: HeaderType::Stdout,
-          request_id: header.request_id,
-          content_length: size,
+          header_type: HeaderType::Stderr,
+          request_id,
+          content_length: 0,
           padding_length: 0,
           reserved: 0,
         },
-        data: ContentData::Raw(Rc::clone(&pack), seek, size),
+        data: ContentData::None,
       };
       data.extend_from_slice(&FastCGI::record_array(record)[..]);
-      seek += usize(size);
     }
     let record = Record {
       header: Header {
         version: FASTCGI_VERSION,
         header_type: HeaderType::Stdout,
-        request_id: header.request_id,
+        request_id,
         content_length: 0,
         padding_length: 0,
         reserved: 0,
@@ -505,7 +612,7 @@ impl FastCGI {
       header: Header {
         version: FASTCGI_VERSION,
         header_type: HeaderType::EndRequest,
-        request_id: header.request_id,
+        request_id,
         content_length: 8,
         padding_length: 0,
         reserved: 0,
@@ -517,114 +624,11 @@ impl FastCGI {
       }),
     };
     data.extend_from_slice(&FastCGI::record_array(record)[..]);
-    if let Err(e) = stream.write_all(&data[..]) {
-      return Err(e); 
-    }
-    Ok(())
+    stream.write_all(&data[..])
   }
 
-  // Write error to WEB server, but RUST don't have error )))
-  // pub fn write_error(header: &Header, answer: Vec<u8>, error: Vec<u8>, stream: &mut TcpStream) -> Result<(), Error> {
-  //   let mut data: Vec<u8> = Vec::new();
-  //   // error
-  //   let mut len = answer.len();
-  //   let mut size:u16;
-  //   let mut seek: usize = 0;
-  //   let pack = Arc::new(error);
-  //   while seek < len {
-  //     if seek + FASTCGI_MAX_CONTENT_LEN < len {
-  //       size = u16(FASTCGI_MAX_CONTENT_LEN).unwrap();
-  //     } else {
-  //       size = u16(len).unwrap();
-  //     };
-  //     let record = Record {
-  //       header: Header {
-  //         version: FASTCGI_VERSION,
-  //         header_type: HeaderType::Stderr,
-  //         request_id: header.request_id,
-  //         content_length: size,
-  //         padding_length: 0,
-  //         reserved: 0,
-  //       },
-  //       data: ContentData::Raw(Arc::clone(&pack), seek, size),
-  //     };
-  //     data.extend_from_slice(&FastCGI::record_array(record)[..]);
-  //     seek += usize(size);
-  //   }
-  //   let record = Record {
-  //     header: Header {
-  //       version: FASTCGI_VERSION,
-  //       header_type: HeaderType::Stderr,
-  //       request_id: header.request_id,
-  //       content_length: 0,
-  //       padding_length: 0,
-  //       reserved: 0,
-  //     },
-  //     data: ContentData::None,
-  //   };
-  //   data.extend_from_slice(&FastCGI::record_array(record)[..]);
-  //   // answer
-  //   len = answer.len();
-  //   seek = 0;
-  //   let pack = Arc::new(answer);
-  //   while seek < len {
-  //     if seek + FASTCGI_MAX_CONTENT_LEN < len {
-  //       size = u16(FASTCGI_MAX_CONTENT_LEN).unwrap();
-  //     } else {
-  //       size = u16(len).unwrap();
-  //     };
-  //     let record = Record {
-  //       header: Header {
-  //         version: FASTCGI_VERSION,
-  //         header_type: HeaderType::Stdout,
-  //         request_id: header.request_id,
-  //         content_length: size,
-  //         padding_length: 0,
-  //         reserved: 0,
-  //       },
-  //       data: ContentData::Raw(Arc::clone(&pack), seek, size),
-  //     };
-  //     data.extend_from_slice(&FastCGI::record_array(record)[..]);
-  //     seek += usize(size);
-  //     data.clear();
-  //   }
-  //   let record = Record {
-  //     header: Header {
-  //       version: FASTCGI_VERSION,
-  //       header_type: HeaderType::Stdout,
-  //       request_id: header.request_id,
-  //       content_length: 0,
-  //       padding_length: 0,
-  //       reserved: 0,
-  //     },
-  //     data: ContentData::None,
-  //   };
-  //   data.extend_from_slice(&FastCGI::record_array(record)[..]);
-  //   // end
-  //   let record = Record {
-  //     header: Header {
-  //       version: FASTCGI_VERSION,
-  //       header_type: HeaderType::EndRequest,
-  //       request_id: header.request_id,
-  //       content_length: 8,
-  //       padding_length: 0,
-  //       reserved: 0,
-  //     },
-  //     data: ContentData::End(End{
-  //       code: 0,
-  //       protocol: FASTCGI_REQUEST_COMPLETE,
-  //       reserved: [0; 3],
-  //     }),
-  //   };
-  //   data.extend_from_slice(&FastCGI::record_array(record)[..]);
-  //   if let Err(e) = stream.write_all(&data[..]) {
-  //     return Err(e); 
-  //   }
-  //   Ok(())
-  // }
-
   // Write abore request
-  pub fn write_abort(header: &Header, stream: &mut TcpStream) -> Result<(), Error> {
+  pub fn write_abort<W: Write>(header: &Header, stream: &mut W) -> Result<(), Error> {
     let record = Record {
       header: Header {
         version: FASTCGI_VERSION,
@@ -641,9 +645,7 @@ impl FastCGI {
       }),
     };
     let data = FastCGI::record_array(record);
-    if let Err(e) = stream.write_all(&data[..]) {
-      return Err(e); 
-    }
+    stream.write_all(&data[..])?;
     Ok(())
   }
 
@@ -671,7 +673,7 @@ impl FastCGI {
           if key_len < 128 {
             data.push(u8(key_len).unwrap());
           } else {
-            key_len = key_len | 0x80000000;
+            key_len |= 0x80000000;
             BigEndian::write_u32(&mut buf, key_len);
             data.extend_from_slice(&buf);
           }
@@ -679,21 +681,21 @@ impl FastCGI {
           if value_len < 128 {
             data.push(u8(value_len).unwrap());
           } else {
-            value_len = value_len | 0x80000000;
+            value_len |= 0x80000000;
             BigEndian::write_u32(&mut buf, value_len);
             data.extend_from_slice(&buf);
           }
-          data.extend_from_slice(&key.as_bytes()[..]);
-          data.extend_from_slice(&value.as_bytes()[..]);
+          data.extend_from_slice(key.as_bytes());
+          data.extend_from_slice(value.as_bytes());
         }
+        // The length of the name-value pairs is known only after encoding
+        let len = u16(data.len() - FASTCGI_HEADER_LEN).unwrap();
+        BigEndian::write_u16(&mut data[4..6], len);
       },
       ContentData::Unknown(unknown) => {
         data.push(unknown.unknown_type);
         data.extend_from_slice(&unknown.reserved[0..7]);
       },
-      ContentData::Raw(arr, seek, size) => {
-        data.extend_from_slice(&arr[seek..seek + usize(size)]);
-      },
       ContentData::End(end) => {
         BigEndian::write_u32(&mut buf4, end.code);
         data.extend_from_slice(&buf4);
@@ -704,4 +706,121 @@ impl FastCGI {
     }
     data
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{io::{Cursor, Read}, sync::Mutex};
+
+  use super::*;
+
+  // Raw fastCGI record
+  fn raw(header_type: u8, request_id: u16, content: &[u8], padding: u8) -> Vec<u8> {
+    let mut data = vec![FASTCGI_VERSION, header_type, 0, 0, 0, 0, padding, 0];
+    BigEndian::write_u16(&mut data[2..4], request_id);
+    BigEndian::write_u16(&mut data[4..6], u16(content.len()).unwrap());
+    data.extend_from_slice(content);
+    data.resize(data.len() + usize::from(padding), 0);
+    data
+  }
+
+  // Stream that gives the data by small parts, like a slow WEB server
+  struct Parts {
+    data: Vec<u8>,
+    seek: usize,
+    part: usize,
+  }
+
+  impl Read for Parts {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+      let len = self.part.min(buf.len()).min(self.data.len() - self.seek);
+      buf[..len].copy_from_slice(&self.data[self.seek..self.seek + len]);
+      self.seek += len;
+      Ok(len)
+    }
+  }
+
+  // Read the next record from the stream
+  fn next<S: Read>(seek: &mut usize, size: &mut usize, need_read: &mut bool, buffer: &mut [u8], stream: &mut S) -> RecordType {
+    let writer = Mutex::new(Vec::new());
+    FastCGI::read_record(seek, size, need_read, buffer, stream, &writer, 1)
+  }
+
+  // Content of the stdin record
+  fn stdin(record: RecordType) -> Vec<u8> {
+    match record {
+      RecordType::Some(Record { header: Header { header_type: HeaderType::Stdin, .. }, data: ContentData::Stream(data) }) => data,
+      _ => panic!("stdin record isn't read"),
+    }
+  }
+
+  #[test]
+  fn timeout_after_end_writes_nothing() {
+    let writer = Arc::new(Mutex::new(Vec::new()));
+    let mut output = Output::new(Arc::clone(&writer), 1);
+    assert!(output.stdout(b"Status: 200 OK\r\n\r\n"));
+    assert!(output.end());
+    let len = Mutex::lock(&writer).unwrap().len();
+    output.timeout(b"Status: 504 Gateway Timeout\r\n\r\n");
+    assert!(!output.end());
+    assert_eq!(Mutex::lock(&writer).unwrap().len(), len);
+  }
+
+  #[test]
+  fn end_after_timeout_writes_nothing() {
+    let writer = Arc::new(Mutex::new(Vec::new()));
+    let mut output = Output::new(Arc::clone(&writer), 1);
+    output.timeout(b"Status: 504 Gateway Timeout\r\n\r\n");
+    let len = Mutex::lock(&writer).unwrap().len();
+    assert!(len > 0);
+    assert!(!output.end());
+    assert_eq!(Mutex::lock(&writer).unwrap().len(), len);
+  }
+
+  #[test]
+  fn record_ends_at_buffer_edge() {
+    let content = vec![7; 1000];
+    let data = raw(FASTCGI_STDIN, 1, &content, 0);
+    let mut buffer = vec![0; data.len()];
+    let (mut seek, mut size, mut need_read) = (0, 0, true);
+    let mut stream = Cursor::new(data);
+    assert_eq!(stdin(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream)), content);
+    assert_eq!(seek, buffer.len());
+    assert!(matches!(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream), RecordType::ErrorStream));
+  }
+
+  #[test]
+  fn padded_record_ends_at_buffer_edge() {
+    // The first record moves the second one to the start of the buffer, which then ends at its edge
+    let first = raw(FASTCGI_STDIN, 1, &[1; 10], 6);
+    let second = raw(FASTCGI_STDIN, 1, &[2; 100], 4);
+    let mut buffer = vec![0; second.len()];
+    let mut data = first;
+    data.extend_from_slice(&second);
+    let (mut seek, mut size, mut need_read) = (0, 0, true);
+    let mut stream = Parts { data, seek: 0, part: 16 };
+    assert_eq!(stdin(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream)), vec![1; 10]);
+    assert_eq!(stdin(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream)), vec![2; 100]);
+    assert_eq!(size, buffer.len());
+  }
+
+  #[test]
+  fn record_truncated_by_one_byte() {
+    let data = raw(FASTCGI_STDIN, 1, &[7; 1000], 0);
+    let mut buffer = vec![0; FASTCGI_MAX_REQUEST_LEN];
+    let (mut seek, mut size, mut need_read) = (0, 0, true);
+    let mut stream = Cursor::new(&data[..data.len() - 1]);
+    assert!(matches!(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream), RecordType::ErrorStream));
+    assert_eq!(seek, 0);
+  }
+
+  #[test]
+  fn padding_truncated_by_one_byte() {
+    let data = raw(FASTCGI_STDIN, 1, &[7; 1000], 8);
+    let mut buffer = vec![0; FASTCGI_MAX_REQUEST_LEN];
+    let (mut seek, mut size, mut need_read) = (0, 0, true);
+    let mut stream = Cursor::new(&data[..data.len() - 1]);
+    assert!(matches!(next(&mut seek, &mut size, &mut need_read, &mut buffer, &mut stream), RecordType::ErrorStream));
+    assert_eq!(seek, 0);
+  }
+}
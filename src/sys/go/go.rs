@@ -1,390 +1,889 @@
-use std::{net::{TcpListener, TcpStream, Shutdown}, time::Duration, thread::{JoinHandle, self}, io::{Read, Write, ErrorKind}, sync::mpsc};
+use std::{fs, net::{Shutdown, SocketAddr}, time::{Duration, Instant}, thread::{JoinHandle, self}, io::{ErrorKind, Error}, sync::mpsc::{self, RecvTimeoutError}};
+
+use postgres::NoTls;
 use std::{sync::{Arc, Mutex, RwLock}};
 
-use crate::sys::{init::Init, log::LogApp};
+use crate::sys::{init::{Init, Bind}, log::LogApp, error::TryteexError, irc::{Irc, IrcAnswer, IrcChallenge, IrcCommand, IrcFault, IrcRequest, IRC_VERSION, IRC_REQUEST_LEN, IRC_DEADLINE}};
+
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
 
-use super::{worker::{Worker, Message}, storage::Storage, i18n::I18n, template::Template, queue::Queue};
+use super::{sys::Sys, db::{Db, DbPool}, handover::Handover, fastcgi::{FastCGI, RecordType, HeaderType, FASTCGI_MAX_REQUEST_LEN}, worker::{Worker, Message}, storage::Storage, snapshot::Snapshot, signal::Signal, systemd::Systemd, queue::Queue, idle::Idle, stream::{Listener, Stream, Deadline}};
+
+// Running worker and the channel to send it messages
+pub type Connection = (Arc<Mutex<Worker>>, mpsc::Sender<Message>);
 
-pub const MS1: std::time::Duration = Duration::from_millis(1);
 // Main struct for program
 pub struct Go {
   pub init: Arc<RwLock<Init>>,                                      // Init system
   pub log: Arc<RwLock<LogApp>>,                                     // Log system
-  pub tcp: Option<JoinHandle<()>>,                                  // TCP reciever
+  pub listen: Vec<JoinHandle<()>>,                                  // Socket recievers
+  wake: Vec<Bind>,                                                  // Addresses to wake up the socket recievers
+  sockets: Vec<(&'static str, i32)>,                                // Descriptors of the sockets for the new server at the restart
+  handover: Arc<Mutex<bool>>,                                       // The sockets are handed over to the new server
   pub main: Option<JoinHandle<()>>,                                 // Main thread
+  watchdog: Option<(JoinHandle<()>, mpsc::Sender<()>)>,             // Watchdog thread for the hung workers
+  watch: Option<(JoinHandle<()>, mpsc::Sender<()>)>,                // Thread to reload the changed templates and translations
   stop: bool,                                                       // Send the "stop" signal
+  started: Instant,                                                 // Time of the start
+  served: usize,                                                    // Completed requests of the stopped workers
+  recycled: usize,                                                  // Number of the hung workers
   max_connection: usize,                                            // Max threads or max connections (it is the same) from the WEB server
+  pub min_workers: usize,                                           // Workers which are always running
   pub use_connection: usize,                                        // How many threads are already running
-  connections: Vec<(Arc<Mutex<Worker>>, mpsc::Sender<Message>)>,    // Connections from the WEB server
+  pub connections: Vec<Option<Connection>>,                         // Connections from the WEB server, None - the worker isn't running
   pub storage: Arc<Mutex<Storage>>,                                 // Memory cache system
-  pub i18n: Arc<Mutex<I18n>>,                                       // Translations
-  pub tpl: Arc<Mutex<Template>>,                                    // Templates system
-  queue: Arc<Mutex<Queue>>,                                         // Input connections
+  pub snapshot: Arc<Mutex<Arc<Snapshot>>>,                         // Translations and templates for the next request
+  queue: Arc<Queue>,                                                // Input connections
+  pub idle: Arc<Idle>,                                              // Free workers
+  pub db: Arc<DbPool>,                                              // Connections to the database
 }
 
 impl Go {
   // Start fastCGI and CRM server
-  pub fn start(init: Arc<RwLock<Init>>, log: Arc<RwLock<LogApp>>) {
+  pub fn start(init: Arc<RwLock<Init>>, log: Arc<RwLock<LogApp>>) -> Result<(), TryteexError> {
     let init_read = RwLock::read(&init).unwrap();
-    let log_read = RwLock::read(&log).unwrap();
 
     let max_connection = usize::from(init_read.sys.max_connection);
+    let min_workers = usize::from(init_read.sys.min_workers);
 
     let q = Queue::new(65536);
 
+    // The signals are taken by the signal thread, the mask must be set before other threads are started
+    Signal::block()?;
+
+    // The sockets of the previous server at the restart
+    let mut handover = Handover::take()?;
+    // The sockets passed by systemd at the socket activation
+    let mut systemd = Systemd::listen()?;
+
+    // Connections to the database are shared by all workers
+    let (conn, tz) = Db::params(&init_read);
+    // The request waits for a free connection shorter than "handler_timeout", so it gets 503 instead of 504
+    let wait = match init_read.db.pool_wait {
+      0 => Duration::from_secs(init_read.sys.handler_timeout.into()) / 4,
+      wait => Duration::from_secs(wait.into()),
+    };
+    let idle = Duration::from_secs(init_read.db.pool_idle.into());
+    let pool = Arc::new(DbPool::new(usize::from(init_read.db.pool_size), wait, idle, conn, tz));
+    pool.check()?;
+    // Load enable languages, translations and templates
+    let snapshot = Snapshot::load(&pool, &init_read.dir)?;
+
     // Create main struct
     let go = Go {
       init: Arc::clone(&init),
       log: Arc::clone(&log),
-      tcp: None,
+      listen: Vec::new(),
+      wake: Vec::new(),
+      sockets: Vec::new(),
+      handover: Arc::new(Mutex::new(false)),
       main: None,
+      watchdog: None,
+      watch: None,
       stop: false,
+      started: Instant::now(),
+      served: 0,
+      recycled: 0,
       max_connection,
+      min_workers,
       use_connection: 0,
-      connections: Vec::with_capacity(max_connection),
+      connections: (0..max_connection).map(|_| None).collect(),
       storage: Arc::new(Mutex::new(Storage::new())),
-      i18n: Arc::new(Mutex::new(I18n::new())),
-      tpl: Arc::new(Mutex::new(Template::new())),
-      queue: Arc::new(Mutex::new(q)),
+      snapshot: Arc::new(Mutex::new(Arc::new(snapshot))),
+      queue: Arc::new(q),
+      idle: Arc::new(Idle::new(max_connection)),
+      db: pool,
     };
 
+    let handover_read = Arc::clone(&go.handover);
     let go = Arc::new(Mutex::new(go));
    
-    // Create threads
-    for i in 0..max_connection {
-      let (sender, receiver) = mpsc::channel();
-      let receiver = Arc::new(Mutex::new(receiver));
-
-      // Start workers
-      let w = Worker::new(i, Arc::clone(&go), Arc::clone(&receiver));
-      {
-        let mut g = Mutex::lock(&go).unwrap();
-        g.connections.push((w, sender));
-      }
+    // Create threads, other workers are started under load
+    for _ in 0..min_workers {
+      let index = Go::reserve(&go).unwrap();
+      Go::spawn(&go, index)?;
+      let g = Mutex::lock(&go).unwrap();
+      g.idle.push(index);
     }
 
     // Run main thread
     Go::main(Arc::clone(&go));
+    // Run watchdog thread
+    Go::watchdog(Arc::clone(&go), Systemd::watchdog(handover.is_some()));
+    // Run thread to reload the changed templates and translations
+    if init_read.sys.watch > 0 {
+      Go::watch(Arc::clone(&go), Duration::from_secs(init_read.sys.watch.into()));
+    }
     // Start threads to listenning to the connections
-    Go::open(Arc::clone(&go));
+    let (listeners, irc) = match (&mut handover, &mut systemd) {
+      (Some(handover), _) => (std::mem::take(&mut handover.listeners), handover.irc.take()),
+      (None, Some(systemd)) => (std::mem::take(&mut systemd.listeners), systemd.irc.take()),
+      (None, None) => (Go::bind(&go)?, None),
+    };
+    Go::open(Arc::clone(&go), listeners)?;
 
     // Bind IRC channel
-    let irc = match TcpListener::bind(&init_read.sys.irc){
-      Ok(irc) => irc,
-      Err(e) => match e.kind() {
-        ErrorKind::PermissionDenied => log_read.exit_err(&LogApp::get_error(300, "")),
-        ErrorKind::AddrInUse => log_read.exit_err(&LogApp::get_error(301, "")),
-        ErrorKind::AddrNotAvailable => log_read.exit_err(&LogApp::get_error(302, "")),
-        _ => log_read.exit_err(&LogApp::get_error(303, &e.to_string())),
+    let irc = match irc {
+      Some(irc) => irc,
+      None => {
+        let irc = match &init_read.sys.irc {
+          Bind::Tcp(addr) => Listener::bind_tcp(&[*addr]),
+          Bind::Unix(path) => Listener::bind_unix(path, init_read.sys.irc_mode, &init_read.sys.irc_owner),
+        };
+        match irc {
+          Ok(irc) => irc,
+          Err(e) => return Err(match e.kind() {
+            ErrorKind::PermissionDenied => TryteexError::Server(300, "".to_owned()),
+            ErrorKind::AddrInUse => TryteexError::Server(301, "".to_owned()),
+            ErrorKind::AddrNotAvailable => TryteexError::Server(302, "".to_owned()),
+            _ => TryteexError::Io(303, "".to_owned(), e),
+          }),
+        }
       },
     };
+    // The new server writes its PID before the previous one is stopped
+    Go::write_pid(&init_read.sys.pid_file)?;
+    // The previous server can finish its work
+    if let Some(handover) = handover {
+      handover.ready();
+    }
+    // The workers are connected to the database and the templates are loaded
+    Systemd::notify(&format!("READY=1\nMAINPID={}", std::process::id()));
+
+    // The config file can be reloaded by the signal or IRC command
+    let pid_file = init_read.sys.pid_file.clone();
+    let pid = init_read.id;
+    drop(init_read);
 
-    // Set Non_blocking status
-    if let Ok(()) = irc.set_nonblocking(true) {
-      let ms300 = Duration::from_millis(300);
-      // Wait incomming IRC command
-      for stream in irc.incoming() {
-        match stream {
+    // Run thread to handle the signals
+    Go::signals(Arc::clone(&go));
+
+    // Wait incomming IRC command
+    loop {
+      match irc.accept() {
+        Ok(mut stream) => {
+          // The server is stopped by the signal
+          if Mutex::lock(&go).unwrap().stop {
+            break;
+          }
           // Run command
-          Ok(mut stream) => match Go::run_command(Arc::clone(&go), &mut stream) {
+          match Go::run_command(Arc::clone(&go), &irc, &mut stream) {
             Some(()) => {},
             None => break,
+          }
+        },
+        Err(_) => continue,
+      };
+    }
+    // The socket file of IRC is left to the new server
+    if *Mutex::lock(&handover_read).unwrap() {
+      irc.release();
+    }
+    Go::remove_pid(&pid_file, pid);
+    Ok(())
+  }
+
+  // Write PID of the server to the file
+  fn write_pid(file: &str) -> Result<(), TryteexError> {
+    if file.is_empty() {
+      return Ok(());
+    }
+    fs::write(file, format!("{}\n", std::process::id())).map_err(|e| TryteexError::Io(202, file.to_owned(), e))
+  }
+
+  // Remove the PID file, if it isn't overwritten by the new server at the restart
+  fn remove_pid(file: &str, pid: u32) {
+    if file.is_empty() {
+      return;
+    }
+    if let Ok(text) = fs::read_to_string(file) {
+      if text.trim() == pid.to_string() {
+        fs::remove_file(file).unwrap_or(());
+      }
+    }
+  }
+
+  // Start the thread which handles the signals of the operating system
+  // It isn't joined, it waits for the signals until the process exits
+  fn signals(go: Arc<Mutex<Go>>) {
+    thread::spawn(move || {
+      while let Some(signal) = Signal::wait() {
+        match signal {
+          Signal::Stop => {
+            Go::stop(Arc::clone(&go));
+            // Wake up the IRC channel to finish the server
+            let g = Mutex::lock(&go).unwrap();
+            let init = RwLock::read(&g.init).unwrap();
+            if let Ok(stream) = Stream::connect(&init.sys.irc) {
+              stream.shutdown(Shutdown::Both).unwrap_or(());
+            }
+            break;
           },
-          Err(e) => match e.kind() {
-            ErrorKind::WouldBlock => {
-              thread::sleep(ms300);
-              continue;
-            },
-            _ => continue,
+          // The errors are written to the log
+          Signal::Reload => {
+            Go::configure(&go).and_then(|_| Go::reload(&go)).unwrap_or(());
           },
-        };
+          Signal::Reopen => {
+            let log = Arc::clone(&Mutex::lock(&go).unwrap().log);
+            let log_read = RwLock::read(&log).unwrap();
+            if let Err(e) = log_read.reopen() {
+              log_read.write_err(&e);
+            }
+          },
+        }
       }
-    }
+    });
   }
 
   // Run IRC command
-  fn run_command(go: Arc<Mutex<Go>>, stream: &mut TcpStream) -> Option<()> {
-    // Set timeout
-    if let Err(_) = stream.set_read_timeout(Some(Duration::from_secs(1))) {
-      if let Err(_) = stream.shutdown(Shutdown::Both) { }
+  fn run_command(go: Arc<Mutex<Go>>, irc: &Listener, stream: &mut Stream) -> Option<()> {
+    // The whole command must come in time, the slow client can't hold the IRC channel
+    let deadline = Instant::now() + Duration::from_secs(IRC_DEADLINE);
+    if stream.set_write_timeout(Some(Duration::from_secs(IRC_DEADLINE))).is_err() {
+      stream.shutdown(Shutdown::Both).unwrap_or(());
       return Some(());
     }
-    let mut buffer: [u8; 1024] = [0; 1024];
+    // Send the challenge, the command must be signed with it
+    let nonce = Irc::nonce();
+    let key;
+    {
+      let g = Mutex::lock(&go).unwrap();
+      let init_read = RwLock::read(&g.init).unwrap();
+      key = init_read.sys.irc_key.clone();
+      let challenge = IrcChallenge { version: IRC_VERSION, pid: init_read.id, nonce: nonce.clone() };
+      if Irc::write(stream, &challenge).is_err() {
+        stream.shutdown(Shutdown::Both).unwrap_or(());
+        return Some(());
+      }
+    }
     // Read and decode command
-    match stream.read(&mut buffer) {
-      Ok(size) => match size {
-        0 => {
-          if let Err(_) = stream.shutdown(Shutdown::Both) { }
-          return Some(());
-        },
-        _ => {
-          let data = &buffer[..size];
-          match std::str::from_utf8(&data) {
-            Ok(data) => {
-              let mut com = data.split(' ').enumerate();
-              let _irc_id = match com.next() {
-                Some(irc_id) => match irc_id.1 {
-                  "" => return Some(()),
-                  text => match text.parse::<u16>() {
-                    Ok(val) => match val {
-                      0 => return Some(()),
-                      id => id,
-                    },
-                    Err(_) => return Some(()),
-                  }
-                },
-                None => return Some(()),
-              };
-              // Detecting the command
-              match com.next() {
-                Some(str) => match str.1 {
-                  "stop" => {
-                    // Found stop
-                    Go::stop(Arc::clone(&go));
-                    Go::send_answer(Arc::clone(&go), "stop", stream);
-                    return None; 
-                  },
-                  _ => return Some(()),
-                },
-                None => return Some(()),
-              }
-            },
-            Err(_) => {
-              if let Err(_) = stream.shutdown(Shutdown::Both) { }
-              return Some(());
-            },
-          }
-        },
+    let request: IrcRequest = match Irc::read(&mut Deadline::new(stream, deadline), IRC_REQUEST_LEN) {
+      Ok(request) => request,
+      Err(e) => {
+        if e.kind() == ErrorKind::InvalidData {
+          Go::reply(&go, stream, None, Err((IrcFault::Format, e.to_string())));
+        }
+        stream.shutdown(Shutdown::Both).unwrap_or(());
+        return Some(());
       },
-      Err(_) => return Some(()),
     };
+    if request.version != IRC_VERSION {
+      let text = format!("version {} isn't supported, expected {}", request.version, IRC_VERSION);
+      Go::reply(&go, stream, Some(request.command), Err((IrcFault::Version, text)));
+      return Some(());
+    }
+    // Check the signature
+    if !Irc::verify(&key, &nonce, &request.signed(), &request.sign) {
+      Go::deny(&go, &request, stream);
+      return Some(());
+    }
+    match request.command {
+      IrcCommand::Stop => {
+        Go::stop(Arc::clone(&go));
+        Go::reply(&go, stream, Some(request.command), Ok(String::new()));
+        None
+      },
+      IrcCommand::Status | IrcCommand::Workers | IrcCommand::Stats | IrcCommand::Queue => {
+        let text = match request.command {
+          IrcCommand::Status => Go::status(&go),
+          IrcCommand::Workers => Go::workers(&go),
+          IrcCommand::Stats => Go::stats(&go),
+          _ => Go::queue(&go),
+        };
+        Go::reply(&go, stream, Some(request.command), Ok(text));
+        Some(())
+      },
+      IrcCommand::Reload => {
+        // Reload the config file, templates and translations
+        let result = Go::configure(&go).and_then(|_| Go::reload(&go)).map(|_| String::new()).map_err(|e| (IrcFault::Failed, e.to_string()));
+        Go::reply(&go, stream, Some(request.command), result);
+        Some(())
+      },
+      IrcCommand::Restart => {
+        // Start the new server with the same sockets
+        if let Err(e) = Go::handover(Arc::clone(&go), irc) {
+          {
+            let g = Mutex::lock(&go).unwrap();
+            let log_read = RwLock::read(&g.log).unwrap();
+            log_read.write_err(&e);
+          }
+          Go::reply(&go, stream, Some(request.command), Err((IrcFault::Failed, e.to_string())));
+          return Some(());
+        }
+        Go::reply(&go, stream, Some(request.command), Ok(String::new()));
+        stream.shutdown(Shutdown::Both).unwrap_or(());
+        // Finish the requests in progress
+        Go::stop(Arc::clone(&go));
+        None
+      },
+    }
   }
 
   // Stop fastCGI and CRM server
   fn stop(go: Arc<Mutex<Go>>) {
-    let tcp_read;
+    let listen_read;
     let main_read;
+    let watchdog_read;
+    let watch_read;
+    let rest;
+    let grace;
+    let log;
+    let mut workers = Vec::new();
     // Send "stop" to all threads
     {
       let mut g = Mutex::lock(&go).unwrap();
+      // The server is already stopping
+      if g.stop {
+        return;
+      }
       g.stop = true;
-      tcp_read = g.tcp.take();
+      // At the restart the new server is already the main process for systemd
+      if !*Mutex::lock(&g.handover).unwrap() {
+        Systemd::notify("STOPPING=1");
+      }
+      listen_read = std::mem::take(&mut g.listen);
       main_read = g.main.take();
-      for i in 0..g.max_connection {
-        let (item, sender) = g.connections.get(i).unwrap();
+      watchdog_read = g.watchdog.take();
+      watch_read = g.watch.take();
+      log = Arc::clone(&g.log);
+      {
+        let init = RwLock::read(&g.init).unwrap();
+        grace = Duration::from_secs(init.sys.grace_period.into());
+      }
+      // Stop accepting, wake up the main thread and the socket recievers
+      rest = g.queue.stop();
+      g.idle.stop();
+      for bind in &g.wake {
+        if let Ok(stream) = Stream::connect(bind) {
+          stream.shutdown(Shutdown::Both).unwrap_or(());
+        }
+      }
+      // The workers finish the requests in progress and then stop
+      for (item, sender) in g.connections.iter().flatten() {
         {
           let mut w = Mutex::lock(item).unwrap();
           w.stop = true;  
         }
-        sender.send(Message::Terminate).unwrap()
+        // The thread of the worker may be already finished
+        sender.send(Message::Terminate).unwrap_or(());
+        workers.push(Arc::clone(item));
       }
     }
-    // Wait while threads aren't stop
+    // The connections from the queue aren't served
+    for stream in rest {
+      Go::reject(stream);
+    }
+    // Wait while threads aren't stop, but not longer than the grace period
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && !workers.iter().all(Worker::is_finished) {
+      thread::sleep(Duration::from_millis(10));
+    }
+    let (done, hung): (Vec<_>, Vec<_>) = workers.into_iter().partition(Worker::is_finished);
+    for item in done {
+      Worker::join(item);
+    }
+    if !hung.is_empty() {
+      let log_read = RwLock::read(&log).unwrap();
+      log_read.write_err(&TryteexError::Server(507, hung.len().to_string()));
+    }
+    for listen in listen_read {
+      listen.join().unwrap();
+    }
+    if let Some(main) = main_read {
+      main.join().unwrap();
+    }
+    // The watchdog stops when the sender is dropped
+    if let Some((watchdog, sender)) = watchdog_read {
+      drop(sender);
+      watchdog.join().unwrap();
+    }
+    if let Some((watch, sender)) = watch_read {
+      drop(sender);
+      watch.join().unwrap();
+    }
+  }
+
+  // Hand over the sockets to the new server and stop accepting the connections
+  fn handover(go: Arc<Mutex<Go>>, irc: &Listener) -> Result<(), TryteexError> {
+    let exe;
+    let flags;
+    let sockets;
     {
       let g = Mutex::lock(&go).unwrap();
-      for i in 0..g.max_connection {
-        let (item, _) = g.connections.get(i).unwrap();
-        Worker::join(Arc::clone(item));
+      let init = RwLock::read(&g.init).unwrap();
+      exe = init.exe.clone();
+      flags = init.flags.clone();
+      sockets = g.sockets.clone();
+    }
+    // Wait while the workers of the new server load templates and translations
+    Handover::spawn(&exe, &flags, irc, &sockets)?;
+
+    let listen;
+    let wake;
+    let queue;
+    let grace;
+    {
+      let mut g = Mutex::lock(&go).unwrap();
+      *Mutex::lock(&g.handover).unwrap() = true;
+      listen = std::mem::take(&mut g.listen);
+      wake = std::mem::take(&mut g.wake);
+      queue = Arc::clone(&g.queue);
+      let init = RwLock::read(&g.init).unwrap();
+      grace = Duration::from_secs(init.sys.grace_period.into());
+    }
+    // Both servers are accepting now, so the waking connection can get to the new one.
+    // Repeat it while the socket recievers of this server aren't stopped.
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && !listen.iter().all(JoinHandle::is_finished) {
+      for (thread, bind) in listen.iter().zip(wake.iter()) {
+        if !thread.is_finished() {
+          if let Ok(stream) = Stream::connect(bind) {
+            stream.shutdown(Shutdown::Both).unwrap_or(());
+          }
+        }
       }
+      thread::sleep(Duration::from_millis(10));
     }
-    if let Some(tcp) = tcp_read {
-      tcp.join().unwrap();
+    // The accepted connections are served by this server
+    while Instant::now() < deadline && !queue.is_empty() {
+      thread::sleep(Duration::from_millis(10));
     }
-    if let Some(main) = main_read {
-      main.join().unwrap();
+    let mut g = Mutex::lock(&go).unwrap();
+    g.listen = listen;
+    Ok(())
+  }
+
+  // Answer the connection, which isn't served because of the stop, with 503
+  fn reject(mut stream: Stream) {
+    if stream.set_nonblocking(false).is_err() || stream.set_read_timeout(Some(Duration::from_secs(1))).is_err() {
+      return;
     }
+    let writer = match stream.try_clone() {
+      Ok(writer) => Mutex::new(writer),
+      Err(_) => return,
+    };
+    let mut buffer = vec![0; FASTCGI_MAX_REQUEST_LEN];
+    let mut seek: usize = 0;
+    let mut size: usize = 0;
+    let mut need_read = true;
+    // Wait for the "Begin" record to know the request ID
+    loop {
+      match FastCGI::read_record(&mut seek, &mut size, &mut need_read, &mut buffer[..], &mut stream, &writer, 1) {
+        RecordType::None => continue,
+        RecordType::Some(record) => if let HeaderType::BeginRequest = record.header.header_type {
+          let page = Sys::page(503, "");
+          let mut writer = Mutex::lock(&writer).unwrap();
+          if FastCGI::write_stdout(record.header.request_id, page.as_bytes(), &mut *writer).is_ok() {
+            FastCGI::write_end(record.header.request_id, false, &mut *writer).unwrap_or(());
+          }
+          break;
+        },
+        _ => break,
+      }
+    }
+    stream.shutdown(Shutdown::Both).unwrap_or(());
   }
 
   // Send IRC answer
-  fn send_answer(go: Arc<Mutex<Go>>, str: &str, stream: &mut TcpStream) {
-    let answer;
+  fn reply(go: &Arc<Mutex<Go>>, stream: &mut Stream, command: Option<IrcCommand>, result: Result<String, (IrcFault, String)>) {
+    let pid;
     {
-      let g = Mutex::lock(&go).unwrap();
+      let g = Mutex::lock(go).unwrap();
       let init_read = RwLock::read(&g.init).unwrap();
-      answer = format!("{} {} ok:", init_read.id, str);
+      pid = init_read.id;
     }
-    if let Err(_) = stream.write_all(&answer.into_bytes()) { }
+    let answer = match result {
+      Ok(data) => IrcAnswer::ok(pid, command, data),
+      Err((kind, text)) => IrcAnswer::error(pid, command, kind, &text),
+    };
+    Irc::write(stream, &answer).unwrap_or(());
   }
 
-  // Main loop to recieve tcp connection from WEB server
-  pub fn open(go: Arc<Mutex<Go>>) {
-    let move_go = Arc::clone(&go);
-    // Start thread for listening connections from WEB server
-    let tcp = thread::spawn(move || {
-      let bind;
-      // Bind the connection
-      {
-        let g = Mutex::lock(&move_go).unwrap();
-        let init_read = RwLock::read(&g.init).unwrap();
-        bind = TcpListener::bind(&init_read.sys.socket[..]);
-      }
-      let bind = match bind{
-        Ok(bind) => bind,
-        Err(e) => match e.kind() {
-          ErrorKind::PermissionDenied => {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(400, ""));
-          },
-          ErrorKind::AddrInUse => {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(401, ""));
-          },
-          ErrorKind::AddrNotAvailable => {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(402, ""));
-          },
-          _ => {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(403, &e.to_string()));
-          },
-        },
+  // Reject the command with the wrong signature
+  fn deny(go: &Arc<Mutex<Go>>, request: &IrcRequest, stream: &mut Stream) {
+    {
+      let g = Mutex::lock(go).unwrap();
+      let log_read = RwLock::read(&g.log).unwrap();
+      log_read.write_err(&TryteexError::Server(304, format!("{} {:?}", stream.peer(), request.command)));
+    }
+    Go::reply(go, stream, Some(request.command), Err((IrcFault::Access, "access denied".to_owned())));
+  }
+
+  // Summary of the server
+  fn status(go: &Arc<Mutex<Go>>) -> String {
+    let g = Mutex::lock(go).unwrap();
+    let init = RwLock::read(&g.init).unwrap();
+    let (len, _, _) = g.queue.info();
+    let mut text = String::new();
+    text.push_str(&format!("pid: {}\n", init.id));
+    text.push_str(&format!("version: {}\n", init.version));
+    text.push_str(&format!("uptime: {} sec\n", g.started.elapsed().as_secs()));
+    text.push_str(&format!("workers: {} (min {}, max {})\n", g.use_connection, g.min_workers, g.max_connection));
+    text.push_str(&format!("idle: {}\n", g.idle.count()));
+    text.push_str(&format!("queue: {}\n", len));
+    text
+  }
+
+  // State of the running workers
+  fn workers(go: &Arc<Mutex<Go>>) -> String {
+    let g = Mutex::lock(go).unwrap();
+    let now = Instant::now();
+    let mut text = format!("{:<6} {:<9} {:>8} {:>10} {:>9}\n", "id", "status", "count", "total", "deadline");
+    for (item, _) in g.connections.iter().flatten() {
+      let w = Mutex::lock(item).unwrap();
+      let deadline = match w.deadline {
+        Some(deadline) => format!("{} sec", deadline.saturating_duration_since(now).as_secs()),
+        None => "-".to_owned(),
       };
-  
-      // Set Non blocking mode 
-      if let Ok(()) = bind.set_nonblocking(true) {
+      text.push_str(&format!("{:<6} {:<9} {:>8} {:>10} {:>9}\n", w.id, format!("{:?}", w.status), w.count, w.total, deadline));
+    }
+    text
+  }
+
+  // Totals since the start of the server
+  fn stats(go: &Arc<Mutex<Go>>) -> String {
+    let g = Mutex::lock(go).unwrap();
+    let (_, _, count) = g.queue.info();
+    let mut requests = g.served;
+    for (item, _) in g.connections.iter().flatten() {
+      requests += Mutex::lock(item).unwrap().total;
+    }
+    let mut text = String::new();
+    text.push_str(&format!("uptime: {} sec\n", g.started.elapsed().as_secs()));
+    text.push_str(&format!("connections: {}\n", count));
+    text.push_str(&format!("requests: {}\n", requests));
+    text.push_str(&format!("recycled: {}\n", g.recycled));
+    text
+  }
+
+  // State of the queue of the connections
+  fn queue(go: &Arc<Mutex<Go>>) -> String {
+    let g = Mutex::lock(go).unwrap();
+    let (len, max, count) = g.queue.info();
+    format!("len: {}\nmax: {}\ntotal: {}\n", len, max, count)
+  }
+
+  // Bind the sockets to listen to the WEB server
+  fn bind(go: &Arc<Mutex<Go>>) -> Result<Vec<Listener>, TryteexError> {
+    let g = Mutex::lock(go).unwrap();
+    let init_read = RwLock::read(&g.init).unwrap();
+    let mut listeners: Vec<Listener> = Vec::with_capacity(init_read.sys.socket.len());
+    // All TCP addresses are bound by one listener, as before
+    let tcp: Vec<SocketAddr> = init_read.sys.socket.iter().filter_map(|bind| match bind {
+      Bind::Tcp(addr) => Some(*addr),
+      Bind::Unix(_) => None,
+    }).collect();
+    if !tcp.is_empty() {
+      listeners.push(Listener::bind_tcp(&tcp[..]).map_err(Go::bind_error)?);
+    }
+    for bind in &init_read.sys.socket {
+      if let Bind::Unix(path) = bind {
+        listeners.push(Listener::bind_unix(path, init_read.sys.socket_mode, &init_read.sys.socket_owner).map_err(Go::bind_error)?);
+      }
+    }
+    Ok(listeners)
+  }
+
+  // Error of binding the socket
+  fn bind_error(e: Error) -> TryteexError {
+    match e.kind() {
+      ErrorKind::PermissionDenied => TryteexError::Server(400, "".to_owned()),
+      ErrorKind::AddrInUse => TryteexError::Server(401, "".to_owned()),
+      ErrorKind::AddrNotAvailable => TryteexError::Server(402, "".to_owned()),
+      ErrorKind::NotFound | ErrorKind::InvalidInput => TryteexError::Io(404, "".to_owned(), e),
+      _ => TryteexError::Io(403, "".to_owned(), e),
+    }
+  }
+
+  // Main loop to recieve connections from WEB server
+  pub fn open(go: Arc<Mutex<Go>>, listeners: Vec<Listener>) -> Result<(), TryteexError> {
+    let mut threads: Vec<JoinHandle<()>> = Vec::with_capacity(listeners.len());
+    let mut wake: Vec<Bind> = Vec::with_capacity(listeners.len());
+    let mut sockets: Vec<(&'static str, i32)> = Vec::with_capacity(listeners.len());
+    let queue;
+    let handover;
+    {
+      let g = Mutex::lock(&go).unwrap();
+      queue = Arc::clone(&g.queue);
+      handover = Arc::clone(&g.handover);
+    }
+    for bind in listeners {
+      #[cfg(unix)]
+      sockets.push((bind.kind(), bind.as_raw_fd()));
+      wake.push(bind.local().map_err(Go::bind_error)?);
+      let queue = Arc::clone(&queue);
+      let handover = Arc::clone(&handover);
+      // Start thread for listening connections from WEB server
+      let listen = thread::spawn(move || {
         // Main part on loop. Wait incomming request from WEB server
-        for stream in bind.incoming() {
-          // Check the stop
-          {
-            let g = Mutex::lock(&move_go).unwrap();
-            if g.stop == true {
+        // The "stop" command wakes up this thread by a connection to the socket
+        loop {
+          match bind.accept() {
+            Ok(stream) => if queue.push(stream).is_some() {
               break;
-            }
-          }
-          match stream {
-            Ok(stream) => {
-              let mut str = stream;
-              let mut queue;
-              loop {
-                {
-                  let g = Mutex::lock(&move_go).unwrap(); 
-                  if g.stop == true {
-                    break;
-                  }
-                  queue = Arc::clone(&g.queue);
-                }
-                {
-                  let mut q = Mutex::lock(&queue).unwrap(); 
-                  match q.push(str) {
-                    Some(s) => str = s,
-                    None => break,
-                  }
-                }
-                thread::sleep(MS1);
-              }
             },
-            Err(e) => match e.kind() {
-              ErrorKind::WouldBlock => {
-                {
-                  let g = Mutex::lock(&move_go).unwrap();
-                  if g.stop == true {
-                    break;
-                  }
-                }
-                thread::sleep(MS1);
-              },
-              _ => {},
+            Err(_) => if queue.is_stop() {
+              break;
             },
-          };
+          }
+          // The socket is handed over to the new server, it gets the socket file as well
+          if *Mutex::lock(&handover).unwrap() {
+            bind.release();
+            break;
+          }
         }
-      }
-    });
+      });
+      threads.push(listen);
+    }
     let mut g = Mutex::lock(&go).unwrap();
-    g.tcp = Some(tcp);
+    g.listen = threads;
+    g.wake = wake;
+    g.sockets = sockets;
+    Ok(())
   }
 
   // Main loop to strating fastCGI and CRM server
   pub fn main(go: Arc<Mutex<Go>>) {
+    let queue;
+    let idle;
+    {
+      let g = Mutex::lock(&go).unwrap();
+      queue = Arc::clone(&g.queue);
+      idle = Arc::clone(&g.idle);
+    }
     let move_go = Arc::clone(&go);
-    // Start thread for listening connections from WEB server
-    let mut wait = false;
+    // Start thread for handing over the connections to the workers
     let main = thread::spawn(move || loop {
-      if wait {
-        thread::sleep(MS1);
-      }
-      let tcp;
-      let queue;
-      {
-        let g = Mutex::lock(&move_go).unwrap();
-        if g.stop == true {
+      // Wait the connection
+      let stream = match queue.take() {
+        Some(stream) => stream,
+        None => break,
+      };
+      // Find the free thread, otherwise start a new one or wait
+      // We send signal for this sleeping thread
+      let index = match idle.try_take().or_else(|| Go::grow(&move_go)).or_else(|| idle.take()) {
+        Some(index) => index,
+        None => {
+          Go::reject(stream);
           break;
+        },
+      };
+      Go::send(Arc::clone(&move_go), index, Message::Job(stream));
+    });
+    let mut g = Mutex::lock(&go).unwrap();
+    g.main = Some(main);
+  }
+
+  // Start a new worker under load
+  fn grow(go: &Arc<Mutex<Go>>) -> Option<usize> {
+    let index = Go::reserve(go)?;
+    match Go::spawn(go, index) {
+      Ok(()) => Some(index),
+      Err(e) => {
+        let g = Mutex::lock(go).unwrap();
+        let log_read = RwLock::read(&g.log).unwrap();
+        log_read.write_err(&e);
+        None
+      },
+    }
+  }
+
+  // Reserve a place for a new worker
+  fn reserve(go: &Arc<Mutex<Go>>) -> Option<usize> {
+    let mut g = Mutex::lock(go).unwrap();
+    if g.stop || g.use_connection >= g.max_connection {
+      return None;
+    }
+    let index = g.connections.iter().position(|item| item.is_none())?;
+    g.use_connection += 1;
+    Some(index)
+  }
+
+  // Start a new worker in the reserved place
+  fn spawn(go: &Arc<Mutex<Go>>, index: usize) -> Result<(), TryteexError> {
+    let (sender, receiver) = mpsc::channel();
+    let receiver = Arc::new(Mutex::new(receiver));
+    match Worker::new(index, Arc::clone(go), receiver) {
+      Ok(w) => {
+        let mut g = Mutex::lock(go).unwrap();
+        g.connections[index] = Some((w, sender));
+        Ok(())
+      },
+      Err(e) => {
+        let mut g = Mutex::lock(go).unwrap();
+        g.use_connection -= 1;
+        Err(e)
+      },
+    }
+  }
+
+  // Stop the idle worker if there are more workers than the minimum
+  pub fn retire(go: &Arc<Mutex<Go>>, index: usize) -> bool {
+    let mut g = Mutex::lock(go).unwrap();
+    if g.stop || g.use_connection <= g.min_workers || !g.idle.remove(index) {
+      return false;
+    }
+    if let Some((item, _)) = &g.connections[index] {
+      let total = Mutex::lock(item).unwrap().total;
+      g.served += total;
+    }
+    g.use_connection -= 1;
+    g.connections[index] = None;
+    true
+  }
+
+  // Start the thread which checks deadlines of the workers every second and notifies the watchdog of systemd
+  fn watchdog(go: Arc<Mutex<Go>>, notify: Option<Duration>) {
+    let (sender, receiver) = mpsc::channel::<()>();
+    let move_go = Arc::clone(&go);
+    let tick = notify.map_or(Duration::from_secs(1), |notify| notify.min(Duration::from_secs(1)));
+    let watchdog = thread::spawn(move || {
+      let mut ping = Instant::now();
+      while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(tick) {
+        Go::overdue(&move_go);
+        // The watchdog of systemd is notified while this thread isn't hung
+        if let Some(notify) = notify {
+          if ping.elapsed() >= notify {
+            Systemd::notify("WATCHDOG=1");
+            ping = Instant::now();
+          }
         }
-        queue = Arc::clone(&g.queue);
       }
-      wait = false;
-      {
-        let mut q = Mutex::lock(&queue).unwrap();
-        if q.empty() {
-          wait = true;
-          continue;
-        } else {
-          if let Some(t) = q.take() {
-            tcp = t;
-          } else {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(502, ""));  
-          }
+    });
+    let mut g = Mutex::lock(&go).unwrap();
+    g.watchdog = Some((watchdog, sender));
+  }
+
+  // Load templates and translations again, the workers take them for the next request
+  // The current ones are kept if the loading fails
+  pub fn reload(go: &Arc<Mutex<Go>>) -> Result<(), TryteexError> {
+    let pool;
+    let dir;
+    let shared;
+    let log;
+    {
+      let g = Mutex::lock(go).unwrap();
+      let init = RwLock::read(&g.init).unwrap();
+      pool = Arc::clone(&g.db);
+      dir = init.dir.clone();
+      shared = Arc::clone(&g.snapshot);
+      log = Arc::clone(&g.log);
+    }
+    match Snapshot::load(&pool, &dir) {
+      Ok(snapshot) => {
+        *Mutex::lock(&shared).unwrap() = Arc::new(snapshot);
+        Ok(())
+      },
+      Err(e) => {
+        let e = e.wrap(509, "");
+        let log_read = RwLock::read(&log).unwrap();
+        log_read.write_err(&e);
+        Err(e)
+      },
+    }
+  }
+
+  // Reload the config file of the running server
+  pub fn configure(go: &Arc<Mutex<Go>>) -> Result<(), TryteexError> {
+    let init;
+    let log;
+    {
+      let g = Mutex::lock(go).unwrap();
+      init = Arc::clone(&g.init);
+      log = Arc::clone(&g.log);
+    }
+    let result = RwLock::write(&init).unwrap().reload().map_err(|e| e.wrap(510, ""));
+    if let Err(e) = &result {
+      let log_read = RwLock::read(&log).unwrap();
+      log_read.write_err(e);
+    }
+    result
+  }
+
+  // Start the thread which reloads templates and translations after their files are changed
+  fn watch(go: Arc<Mutex<Go>>, interval: Duration) {
+    let (sender, receiver) = mpsc::channel::<()>();
+    let dir;
+    {
+      let g = Mutex::lock(&go).unwrap();
+      let init = RwLock::read(&g.init).unwrap();
+      dir = init.dir.clone();
+    }
+    let move_go = Arc::clone(&go);
+    let watch = thread::spawn(move || {
+      let mut stamp = Snapshot::stamp(&dir);
+      while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+        let next = Snapshot::stamp(&dir);
+        if next != stamp {
+          // The error is written to the log, the files are checked again on the next change
+          Go::reload(&move_go).unwrap_or(());
+          stamp = next;
         }
       }
-      let mut index: Option<usize>;
-      let mut use_connection;
-      let max_connection;
+    });
+    let mut g = Mutex::lock(&go).unwrap();
+    g.watch = Some((watch, sender));
+  }
+
+  // Recycle the workers which are out of the deadline
+  // The hung thread is left alone, a new worker is started instead of it under load
+  fn overdue(go: &Arc<Mutex<Go>>) {
+    let now = Instant::now();
+    let mut list = Vec::new();
+    let log;
+    {
+      let mut g = Mutex::lock(go).unwrap();
+      log = Arc::clone(&g.log);
+      for index in 0..g.max_connection {
+        let item = match &g.connections[index] {
+          Some((item, _)) => Arc::clone(item),
+          None => continue,
+        };
+        let mut w = Mutex::lock(&item).unwrap();
+        match w.deadline {
+          Some(deadline) if deadline <= now => {},
+          _ => continue,
+        }
+        w.recycle = true;
+        w.deadline = None;
+        let cancel = Mutex::lock(&w.cancel).unwrap().clone();
+        list.push((w.id, cancel, w.output.take()));
+        let total = w.total;
+        drop(w);
+        g.connections[index] = None;
+        g.use_connection -= 1;
+        g.served += total;
+        g.recycled += 1;
+      }
+    }
+    if list.is_empty() {
+      return;
+    }
+    let page = Sys::page(504, "");
+    for (id, cancel, output) in list {
       {
-        let g = Mutex::lock(&move_go).unwrap();
-        max_connection = g.max_connection;
+        let log_read = RwLock::read(&log).unwrap();
+        log_read.write_err(&TryteexError::Server(504, id.to_string()));
       }
-      loop {
-        index = None;
-        {
-          let g = Mutex::lock(&move_go).unwrap();
-          if g.stop == true {
-            break;
-          }
-          use_connection = g.use_connection;
+      // The hung worker can hold the answer while it writes to the slow WEB server up to "write_timeout",
+      // so the answer is written in another thread and the watchdog keeps checking other workers
+      let page = page.clone();
+      thread::spawn(move || {
+        if let Some(cancel) = cancel {
+          cancel.cancel_query(NoTls).unwrap_or(());
         }
-        // Wait free thread
-        if use_connection < max_connection {
-          {
-            let mut g = Mutex::lock(&move_go).unwrap();
-            g.use_connection += 1;  
-          }
-          // Find thread
-          for i in 0..max_connection {
-            let g = Mutex::lock(&move_go).unwrap();
-            let (item, _) = g.connections.get(i).unwrap();
-            {
-              let mut w = Mutex::lock(item).unwrap();
-              if w.start == false {
-                w.start = true;
-                w.count = 0;
-                index = Some(i);
-                break;
-              }
-            }
-          }
-          if let None = index {
-            let g = Mutex::lock(&move_go).unwrap();
-            let log_read = RwLock::read(&g.log).unwrap();
-            log_read.exit_err(&LogApp::get_error(501, ""));
-          }
-        }
-        // If we found the free thread 
-        // We send signal for this sleeping thread
-        if let Some(ind) = index {
-          let g = Mutex::lock(&move_go).unwrap();
-          let (_, sender) = g.connections.get(ind).unwrap();
-          sender.send(Message::Job(tcp)).unwrap();
-          break;
+        if let Some(output) = output {
+          Mutex::lock(&output).unwrap().timeout(page.as_bytes());
         }
-        thread::sleep(MS1);
-      }
-    });
-    let mut g = Mutex::lock(&go).unwrap();
-    g.main = Some(main);
+      });
+    }
+  }
+
+  // Send a message to the thread
+  pub fn send(go: Arc<Mutex<Go>>, index: usize, message: Message) {
+    let g = Mutex::lock(&go).unwrap();
+    let (_, sender) = g.connections[index].as_ref().unwrap();
+    sender.send(message).unwrap();
   }
 }
@@ -0,0 +1,147 @@
+#[cfg(unix)]
+use std::{env, io::{Read, Write, Error, ErrorKind}, process::Command, time::Duration, os::unix::{io::{AsRawFd, FromRawFd, RawFd}, net::UnixStream, process::CommandExt}};
+
+use crate::sys::error::TryteexError;
+
+use super::stream::Listener;
+
+// Environment variable with the sockets of the previous server. Example: irc:tcp:3,ready:7,tcp:5,unix:6
+#[cfg(unix)]
+const HANDOVER: &str = "TRYTEEX__HANDOVER";
+// Time to start the new server, it is less than the timeout of the IRC client
+#[cfg(unix)]
+const READY_TIMEOUT: u64 = 25;
+
+// Sockets handed over by the previous server at the restart
+pub struct Handover {
+  pub irc: Option<Listener>,                // IRC socket
+  pub listeners: Vec<Listener>,             // Sockets to listen to the WEB server
+  #[cfg(unix)]
+  ready: Option<UnixStream>,                // Signal to the previous server, that the new one is started
+}
+
+#[cfg(unix)]
+impl Handover {
+  // Take the sockets of the previous server, None - the server is started from scratch
+  pub fn take() -> Result<Option<Handover>, TryteexError> {
+    let value = match env::var(HANDOVER) {
+      Ok(value) => value,
+      Err(_) => return Ok(None),
+    };
+    env::remove_var(HANDOVER);
+    let mut handover = Handover {
+      irc: None,
+      listeners: Vec::new(),
+      ready: None,
+    };
+    for item in value.split(',') {
+      let (kind, fd) = match item.rsplit_once(':') {
+        Some((kind, fd)) => match fd.parse::<RawFd>() {
+          Ok(fd) => (kind, fd),
+          Err(_) => return Err(TryteexError::Server(405, item.to_owned())),
+        },
+        None => return Err(TryteexError::Server(405, item.to_owned())),
+      };
+      // The descriptor must be open, it isn't passed to the next restart by accident
+      if let Err(e) = Handover::cloexec(fd, true) {
+        return Err(TryteexError::Io(405, item.to_owned(), e));
+      }
+      // Safety: the descriptor is open and it is handed over only to this process
+      match kind {
+        "ready" => handover.ready = Some(unsafe { UnixStream::from_raw_fd(fd) }),
+        kind if kind.starts_with("irc:") => match Listener::from_fd(&kind[4..], fd) {
+          Ok(listener) => handover.irc = Some(listener),
+          Err(e) => return Err(TryteexError::Io(405, item.to_owned(), e)),
+        },
+        kind => match Listener::from_fd(kind, fd) {
+          Ok(listener) => handover.listeners.push(listener),
+          Err(e) => return Err(TryteexError::Io(405, item.to_owned(), e)),
+        },
+      }
+    }
+    if handover.listeners.is_empty() {
+      return Err(TryteexError::Server(405, value));
+    }
+    Ok(Some(handover))
+  }
+
+  // Report to the previous server, that the workers are started
+  pub fn ready(self) {
+    if let Some(mut ready) = self.ready {
+      ready.write_all(b"ready").unwrap_or(());
+    }
+  }
+
+  // Start the new server with the sockets of this one and wait while its workers are started
+  // The new server is started in the same directory with the same options to find the same config file
+  pub fn spawn(exe: &str, flags: &[String], irc: &Listener, sockets: &[(&str, i32)]) -> Result<(), TryteexError> {
+    let (mut ready, child_ready) = UnixStream::pair().map_err(|e| TryteexError::Io(508, "".to_owned(), e))?;
+    let mut fds = vec![irc.as_raw_fd(), child_ready.as_raw_fd()];
+    let mut value = format!("irc:{}:{},ready:{}", irc.kind(), fds[0], fds[1]);
+    for (kind, fd) in sockets {
+      fds.push(*fd);
+      value.push_str(&format!(",{}:{}", kind, fd));
+    }
+    let mut command = Command::new(exe);
+    command.arg("go").args(flags).env(HANDOVER, value);
+    // Safety: only fcntl is called between fork and exec, it is async-signal-safe
+    unsafe {
+      command.pre_exec(move || {
+        for fd in &fds {
+          Handover::cloexec(*fd, false)?;
+        }
+        Ok(())
+      });
+    }
+    let mut child = command.spawn().map_err(|e| TryteexError::Io(508, exe.to_owned(), e))?;
+    // Only the new server holds the other end, so the reading stops when it exits
+    drop(child_ready);
+    ready.set_read_timeout(Some(Duration::from_secs(READY_TIMEOUT))).map_err(|e| TryteexError::Io(508, "".to_owned(), e))?;
+    let mut buffer = [0; 5];
+    match ready.read(&mut buffer) {
+      Ok(0) => match child.wait() {
+        Ok(status) => Err(TryteexError::Server(508, format!("the new server is stopped, {}", status))),
+        Err(e) => Err(TryteexError::Io(508, "".to_owned(), e)),
+      },
+      Ok(_) => Ok(()),
+      Err(e) => {
+        child.kill().unwrap_or(());
+        child.wait().ok();
+        match e.kind() {
+          ErrorKind::WouldBlock | ErrorKind::TimedOut => Err(TryteexError::Server(508, format!("the new server isn't started in {} sec", READY_TIMEOUT))),
+          _ => Err(TryteexError::Io(508, "".to_owned(), e)),
+        }
+      },
+    }
+  }
+
+  // Set or clear the close-on-exec flag of the descriptor
+  pub fn cloexec(fd: RawFd, on: bool) -> Result<(), Error> {
+    // Safety: fcntl only changes the flags of the descriptor
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFD) };
+    if flags == -1 {
+      return Err(Error::last_os_error());
+    }
+    let flags = if on { flags | libc::FD_CLOEXEC } else { flags & !libc::FD_CLOEXEC };
+    if unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } == -1 {
+      return Err(Error::last_os_error());
+    }
+    Ok(())
+  }
+}
+
+#[cfg(not(unix))]
+impl Handover {
+  // The sockets can't be handed over on this platform
+  pub fn take() -> Result<Option<Handover>, TryteexError> {
+    Ok(None)
+  }
+
+  // Nothing to report
+  pub fn ready(self) {}
+
+  // The restart isn't available on this platform
+  pub fn spawn(_exe: &str, _flags: &[String], _irc: &Listener, _sockets: &[(&str, i32)]) -> Result<(), TryteexError> {
+    Err(TryteexError::Server(508, "the restart isn't supported on this platform".to_owned()))
+  }
+}
@@ -1,10 +1,11 @@
-use std::{collections::HashMap, fs::{read_dir, read_to_string}, io::Error};
+use std::{collections::HashMap, fs::{read_dir, read_to_string}};
 
 use ini_core::{Parser, Item};
 
+use crate::sys::error::TryteexError;
+
 // Translation
 pub struct I18n {
-  pub load: bool,                                                                     // Translation is loaded
   pub langs: Vec<LangItem>,                                                           // Sorted list of langs
   pub langs_code: HashMap<String, u8>,                                                // Lang code to lang ID: "ua"->1, "en"->3
   pub data: HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>>,   // Translations: lang_id->module->class->key->value
@@ -23,24 +24,14 @@ impl I18n {
   // Constructor
   pub fn new() -> I18n {
     I18n {
-      load: false,
       langs: Vec::with_capacity(8),
       langs_code: HashMap::with_capacity(8),
       data: HashMap::with_capacity(8),
     }
   }
 
-  // Clone language settings
-  pub fn clone_lang(&self) -> Vec<LangItem> {
-    let mut langs: Vec<LangItem> = Vec::with_capacity(self.langs.len());
-    for item in &self.langs {
-      langs.push(item.clone());
-    }
-    langs
-  }
-
   // Load translations
-  pub fn load_lang(&mut self, dir: &str) -> Result<(), Error> {
+  pub fn load_lang(&mut self, dir: &str) -> Result<(), TryteexError> {
     // Read dir with application data
     match read_dir(dir) {
       Ok(d1) => {
@@ -98,7 +89,7 @@ impl I18n {
                                                               }
                                                             }
                                                           },
-                                                          Err(e) => return Err(e),
+                                                          Err(e) => return Err(TryteexError::Io(370, p3.path().to_string_lossy().into_owned(), e)),
                                                         };
                                                       }
                                                     }
@@ -107,33 +98,33 @@ impl I18n {
                                                 };
                                               }
                                             },
-                                            Err(e) => return Err(e),
+                                            Err(e) => return Err(TryteexError::Io(370, "".to_owned(), e)),
                                           }
                                         }
                                       },
-                                      Err(e) => return Err(e),
+                                      Err(e) => return Err(TryteexError::Io(370, p2.path().to_string_lossy().into_owned(), e)),
                                     };
                                   },
                                   None => {},
                                 }
                               }
                             },
-                            Err(e) => return Err(e),
+                            Err(e) => return Err(TryteexError::Io(370, "".to_owned(), e)),
                           };
                         }
                       },
-                      Err(e) => return Err(e),
+                      Err(e) => return Err(TryteexError::Io(370, p1.path().to_string_lossy().into_owned(), e)),
                     };
                   },
                   None => {},
                 }
               }
             },
-            Err(e) => return Err(e),
+            Err(e) => return Err(TryteexError::Io(370, "".to_owned(), e)),
           };
         }
       },
-      Err(e) => return Err(e),
+      Err(e) => return Err(TryteexError::Io(370, dir.to_owned(), e)),
     };
     Ok(())
   }
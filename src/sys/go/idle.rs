@@ -0,0 +1,80 @@
+use std::sync::{Mutex, Condvar};
+
+// Stack of the free workers
+// The last released worker is taken first, so its memory and caches are still warm
+pub struct Idle {
+  list: Mutex<Stack>,                 // Free workers
+  cond: Condvar,                      // Signal about a released worker
+}
+
+// Indexes of the free workers
+struct Stack {
+  data: Vec<usize>,                   // Indexes
+  stop: bool,                         // The server is stopping
+}
+
+impl Idle {
+  pub fn new(max: usize) -> Idle {
+    Idle {
+      list: Mutex::new(Stack {
+        data: Vec::with_capacity(max),
+        stop: false,
+      }),
+      cond: Condvar::new(),
+    }
+  }
+
+  // Return the worker to the stack
+  pub fn push(&self, index: usize) {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    list.data.push(index);
+    self.cond.notify_one();
+  }
+
+  // Take a free worker, waiting while all workers are busy
+  // Returns None if the server is stopping
+  pub fn take(&self) -> Option<usize> {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    while !list.stop && list.data.is_empty() {
+      list = self.cond.wait(list).unwrap();
+    }
+    if list.stop {
+      return None;
+    }
+    list.data.pop()
+  }
+
+  // Take a free worker without waiting
+  pub fn try_take(&self) -> Option<usize> {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    if list.stop {
+      return None;
+    }
+    list.data.pop()
+  }
+
+  // Remove the worker, which is going to stop, from the stack
+  // Returns false if the worker has already been taken
+  pub fn remove(&self, index: usize) -> bool {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    match list.data.iter().position(|i| *i == index) {
+      Some(pos) => {
+        list.data.remove(pos);
+        true
+      },
+      None => false,
+    }
+  }
+
+  // Number of the free workers
+  pub fn count(&self) -> usize {
+    Mutex::lock(&self.list).unwrap().data.len()
+  }
+
+  // Wake up all waiting threads, the workers aren't given out anymore
+  pub fn stop(&self) {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    list.stop = true;
+    self.cond.notify_all();
+  }
+}
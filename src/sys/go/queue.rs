@@ -1,61 +1,123 @@
-use std::{net::TcpStream};
+use std::sync::{Mutex, Condvar};
+
+use super::stream::Stream;
 
 // Queue of waiting connection
+// The acceptors wait while the queue is full, the main thread waits while it is empty
 pub struct Queue {
-  count: usize,                         // Max capacity
+  list: Mutex<List>,                  // Connections
+  not_empty: Condvar,                 // Signal for the main thread
+  not_full: Condvar,                  // Signal for the acceptors
+}
+
+// Ring buffer of the connections
+struct List {
+  count: usize,                       // Total number of connections
   max: usize,                         // Max capacity
   len: usize,                         // Queue len
   first: usize,                       // First index of queue
   last: usize,                        // Last index of queue
-  data: Vec<Option<TcpStream>>,       // Data
+  stop: bool,                         // The server is stopping
+  data: Vec<Option<Stream>>,          // Data
 }
 
 impl Queue {
   pub fn new(max: usize) -> Queue {
-    let mut list: Vec<Option<TcpStream>> = Vec::with_capacity(max);
+    let mut list: Vec<Option<Stream>> = Vec::with_capacity(max);
     for _ in 0..max {
       list.push(None);
     }
     Queue {
-      count: 0,
-      max,
-      len: 0,
-      first: 0, 
-      last: max - 1,
-      data: list,
+      list: Mutex::new(List {
+        count: 0,
+        max,
+        len: 0,
+        first: 0, 
+        last: max - 1,
+        stop: false,
+        data: list,
+      }),
+      not_empty: Condvar::new(),
+      not_full: Condvar::new(),
     }
   }
 
-  pub fn empty(&self) -> bool {
-    self.len == 0
-  }
-
-  pub fn push(&mut self, tcp: TcpStream) -> Option<TcpStream> {
-    self.count += 1;
-    if self.len == self.max {
+  // Put the connection to the queue, waiting while the queue is full
+  // Returns the connection back if the server is stopping
+  pub fn push(&self, tcp: Stream) -> Option<Stream> {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    while !list.stop && list.len == list.max {
+      list = self.not_full.wait(list).unwrap();
+    }
+    if list.stop {
       return Some(tcp);
     }
-    self.len += 1;
-    let mut next = self.last + 1;
-    if next == self.max {
+    list.count += 1;
+    list.len += 1;
+    let mut next = list.last + 1;
+    if next == list.max {
       next = 0;
     }
-    self.last = next;
-    self.data[self.last].replace(tcp);
+    list.last = next;
+    list.data[next].replace(tcp);
+    self.not_empty.notify_one();
     None
   }
 
-  pub fn take(&mut self) -> Option<TcpStream> {
-    if self.len == 0 {
+  // Take the first connection, waiting while the queue is empty
+  // Returns None if the server is stopping
+  pub fn take(&self) -> Option<Stream> {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    while !list.stop && list.len == 0 {
+      list = self.not_empty.wait(list).unwrap();
+    }
+    if list.stop {
       return None;
     }
-    self.len -= 1;
-    let v = self.data[self.first].take();
-    let mut next = self.first + 1;
-    if next == self.max {
+    list.len -= 1;
+    let first = list.first;
+    let v = list.data[first].take();
+    let mut next = first + 1;
+    if next == list.max {
       next = 0;
     }
-    self.first = next;
+    list.first = next;
+    self.not_full.notify_one();
     v
   }
-}
\ No newline at end of file
+
+  // There are no waiting connections
+  pub fn is_empty(&self) -> bool {
+    Mutex::lock(&self.list).unwrap().len == 0
+  }
+
+  // Number of the waiting connections, max capacity and total number of connections
+  pub fn info(&self) -> (usize, usize, usize) {
+    let list = Mutex::lock(&self.list).unwrap();
+    (list.len, list.max, list.count)
+  }
+
+  // The server is stopping
+  pub fn is_stop(&self) -> bool {
+    Mutex::lock(&self.list).unwrap().stop
+  }
+
+  // Wake up all waiting threads, the queue doesn't accept connections anymore
+  // Returns the connections, which are still waiting in the queue
+  pub fn stop(&self) -> Vec<Stream> {
+    let mut list = Mutex::lock(&self.list).unwrap();
+    list.stop = true;
+    let mut rest = Vec::with_capacity(list.len);
+    while list.len > 0 {
+      list.len -= 1;
+      let first = list.first;
+      if let Some(stream) = list.data[first].take() {
+        rest.push(stream);
+      }
+      list.first = if first + 1 == list.max { 0 } else { first + 1 };
+    }
+    self.not_empty.notify_all();
+    self.not_full.notify_all();
+    rest
+  }
+}
@@ -0,0 +1,68 @@
+#[cfg(unix)]
+use std::{io::Error, mem, ptr};
+
+use crate::sys::error::TryteexError;
+
+// Signals to manage the running server
+pub enum Signal {
+  Stop,                                 // SIGTERM, SIGINT - stop the server after the requests in progress
+  Reload,                               // SIGHUP - reload the config file, templates and translations
+  Reopen,                               // SIGUSR1 - reopen the log file after the rotation
+}
+
+#[cfg(unix)]
+impl Signal {
+  // Block the signals in this thread, the threads started from it inherit the mask
+  // The signals are taken only by Signal::wait, so they never interrupt the workers
+  pub fn block() -> Result<(), TryteexError> {
+    let set = Signal::set();
+    // Safety: the mask is changed only for the current thread
+    match unsafe { libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut()) } {
+      0 => Ok(()),
+      e => Err(TryteexError::Io(203, "".to_owned(), Error::from_raw_os_error(e))),
+    }
+  }
+
+  // Wait for the next signal
+  pub fn wait() -> Option<Signal> {
+    let set = Signal::set();
+    let mut signal = 0;
+    loop {
+      // Safety: sigwait only writes the number of the signal
+      if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+        break;
+      }
+    }
+    match signal {
+      libc::SIGHUP => Some(Signal::Reload),
+      libc::SIGUSR1 => Some(Signal::Reopen),
+      _ => Some(Signal::Stop),
+    }
+  }
+
+  // Set of the handled signals
+  fn set() -> libc::sigset_t {
+    // Safety: the set is initialized by sigemptyset before it is used
+    unsafe {
+      let mut set: libc::sigset_t = mem::zeroed();
+      libc::sigemptyset(&mut set);
+      for signal in [libc::SIGTERM, libc::SIGINT, libc::SIGHUP, libc::SIGUSR1] {
+        libc::sigaddset(&mut set, signal);
+      }
+      set
+    }
+  }
+}
+
+#[cfg(not(unix))]
+impl Signal {
+  // The signals aren't handled on this platform
+  pub fn block() -> Result<(), TryteexError> {
+    Ok(())
+  }
+
+  // Nothing to wait, the server is managed only by IRC
+  pub fn wait() -> Option<Signal> {
+    None
+  }
+}
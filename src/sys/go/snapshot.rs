@@ -0,0 +1,87 @@
+use std::{fs::read_dir, path::{Path, PathBuf}, sync::{Arc, Mutex}, time::SystemTime};
+
+use cast::u8;
+
+use crate::sys::error::TryteexError;
+
+use super::{db::{DbPool, DbLease}, i18n::{I18n, LangItem}, template::Template};
+
+// Translations and templates shared by all workers
+// The reload builds a new snapshot and swaps it, the running requests keep the previous one
+pub struct Snapshot {
+  pub i18n: I18n,                       // Translations
+  pub tpl: Template,                    // Templates
+}
+
+impl Snapshot {
+  // Load enable languages from the database, translations and templates from the application directory
+  pub fn load(pool: &Arc<DbPool>, dir: &str) -> Result<Snapshot, TryteexError> {
+    let mut i18n = I18n::new();
+    let text = "SELECT lang_id, lang, code, name FROM lang WHERE enable ORDER BY sort";
+    let mut sql = DbLease::new(Arc::clone(pool), Arc::new(Mutex::new(None)));
+    match sql.query(text) {
+      Ok(res) => {
+        for row in res {
+          let lang_id: i64 = row.get(0);
+          let lang_code: String = row.get(1);
+          let code: String = row.get(2);
+          let name: String = row.get(3);
+          let lang_id = u8(lang_id).unwrap();
+          i18n.langs_code.insert(lang_code.clone(), lang_id);
+          let l = LangItem {lang_id, code, lang: lang_code, name, };
+          i18n.langs.push(l);
+        }
+      },
+      Err(e) => return Err(TryteexError::Server(351, e)),
+    };
+    // Read translates
+    i18n.load_lang(dir)?;
+    // Load templates
+    let mut tpl = Template::new();
+    tpl.load_templates(dir)?;
+    Ok(Snapshot { i18n, tpl })
+  }
+
+  // Take the current snapshot for one request
+  pub fn current(shared: &Mutex<Arc<Snapshot>>) -> Arc<Snapshot> {
+    Arc::clone(&Mutex::lock(shared).unwrap())
+  }
+
+  // Number of the files with translations and templates and the time of the last change
+  // It is used to find out that the files were changed
+  pub fn stamp(dir: &str) -> (usize, Option<SystemTime>) {
+    let mut count = 0;
+    let mut last = None;
+    // Files are in the "module/class" directory
+    for module in Snapshot::dirs(Path::new(dir)) {
+      for class in Snapshot::dirs(&module) {
+        let files = match read_dir(&class) {
+          Ok(files) => files,
+          Err(_) => continue,
+        };
+        for file in files.flatten() {
+          let name = file.file_name();
+          let name = name.to_str().unwrap_or("");
+          let view = name.starts_with("view_") && name.ends_with(".html");
+          let lang = name.starts_with("lang_") && name.ends_with(".ini");
+          if !view && !lang {
+            continue;
+          }
+          count += 1;
+          if let Ok(modified) = file.metadata().and_then(|meta| meta.modified()) {
+            last = last.max(Some(modified));
+          }
+        }
+      }
+    }
+    (count, last)
+  }
+
+  // List of the subdirectories
+  fn dirs(dir: &Path) -> Vec<PathBuf> {
+    match read_dir(dir) {
+      Ok(list) => list.flatten().map(|item| item.path()).filter(|path| path.is_dir()).collect(),
+      Err(_) => Vec::new(),
+    }
+  }
+}
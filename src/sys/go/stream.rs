@@ -0,0 +1,350 @@
+use std::{io::{Read, Write, Result, Error, ErrorKind}, net::{TcpListener, TcpStream, Shutdown, SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr}, time::{Duration, Instant}};
+
+use crate::sys::init::Bind;
+#[cfg(unix)]
+use std::{os::unix::{net::{UnixListener, UnixStream}, fs::{PermissionsExt, FileTypeExt, chown}, io::{AsRawFd, FromRawFd, RawFd}}, fs::{set_permissions, remove_file, symlink_metadata, Permissions}, ffi::CString};
+
+// Connection from the WEB server
+pub enum Stream {
+  Tcp(TcpStream),                       // TCP connection
+  #[cfg(unix)]
+  Unix(UnixStream),                     // Unix domain socket connection
+}
+
+// Listening socket for the WEB server
+pub enum Listener {
+  Tcp(TcpListener),                     // TCP socket
+  #[cfg(unix)]
+  Unix(UnixListener, String, bool),     // Unix domain socket, path to the socket file and the file is removed at the close
+}
+
+impl Stream {
+  // Connect to the listening socket
+  pub fn connect(bind: &Bind) -> Result<Stream> {
+    match bind {
+      Bind::Tcp(addr) => Ok(Stream::Tcp(TcpStream::connect(addr)?)),
+      #[cfg(unix)]
+      Bind::Unix(path) => Ok(Stream::Unix(UnixStream::connect(path)?)),
+      #[cfg(not(unix))]
+      Bind::Unix(_) => Err(Error::new(ErrorKind::Unsupported, "unix domain sockets are not supported on this platform")),
+    }
+  }
+
+  // Connect to the listening socket, waiting not longer than the timeout
+  pub fn connect_timeout(bind: &Bind, timeout: Duration) -> Result<Stream> {
+    match bind {
+      Bind::Tcp(addr) => Ok(Stream::Tcp(TcpStream::connect_timeout(addr, timeout)?)),
+      // Connecting to the unix domain socket doesn't wait
+      _ => Stream::connect(bind),
+    }
+  }
+
+  // Address of the other side of the connection
+  pub fn peer(&self) -> String {
+    match self {
+      Stream::Tcp(s) => s.peer_addr().map(|addr| addr.to_string()).unwrap_or_default(),
+      #[cfg(unix)]
+      Stream::Unix(_) => "unix".to_owned(),
+    }
+  }
+
+  // Clone the connection, so it can be read and written from different places
+  pub fn try_clone(&self) -> Result<Stream> {
+    match self {
+      Stream::Tcp(s) => Ok(Stream::Tcp(s.try_clone()?)),
+      #[cfg(unix)]
+      Stream::Unix(s) => Ok(Stream::Unix(s.try_clone()?)),
+    }
+  }
+
+  // Set non-blocking mode
+  pub fn set_nonblocking(&self, nonblocking: bool) -> Result<()> {
+    match self {
+      Stream::Tcp(s) => s.set_nonblocking(nonblocking),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.set_nonblocking(nonblocking),
+    }
+  }
+
+  // Set reading timeout
+  pub fn set_read_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+    match self {
+      Stream::Tcp(s) => s.set_read_timeout(timeout),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.set_read_timeout(timeout),
+    }
+  }
+
+  // Set writing timeout
+  pub fn set_write_timeout(&self, timeout: Option<Duration>) -> Result<()> {
+    match self {
+      Stream::Tcp(s) => s.set_write_timeout(timeout),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.set_write_timeout(timeout),
+    }
+  }
+
+  // Close the connection
+  pub fn shutdown(&self, how: Shutdown) -> Result<()> {
+    match self {
+      Stream::Tcp(s) => s.shutdown(how),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.shutdown(how),
+    }
+  }
+}
+
+impl Read for Stream {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    match self {
+      Stream::Tcp(s) => s.read(buf),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.read(buf),
+    }
+  }
+}
+
+impl Write for Stream {
+  fn write(&mut self, buf: &[u8]) -> Result<usize> {
+    match self {
+      Stream::Tcp(s) => s.write(buf),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.write(buf),
+    }
+  }
+
+  fn flush(&mut self) -> Result<()> {
+    match self {
+      Stream::Tcp(s) => s.flush(),
+      #[cfg(unix)]
+      Stream::Unix(s) => s.flush(),
+    }
+  }
+}
+
+// Reading of the connection, which must be finished by the deadline, so the slow client can't hold it
+pub struct Deadline<'a> {
+  stream: &'a mut Stream,         // Connection
+  until: Instant,                 // Time when the reading is over
+}
+
+impl<'a> Deadline<'a> {
+  // Constructor
+  pub fn new(stream: &'a mut Stream, until: Instant) -> Deadline<'a> {
+    Deadline {
+      stream,
+      until,
+    }
+  }
+}
+
+impl Read for Deadline<'_> {
+  fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+    let left = self.until.saturating_duration_since(Instant::now());
+    if left.is_zero() {
+      return Err(Error::new(ErrorKind::TimedOut, "the deadline is over"));
+    }
+    self.stream.set_read_timeout(Some(left))?;
+    self.stream.read(buf)
+  }
+}
+
+impl Listener {
+  // Bind TCP socket to the first available address
+  pub fn bind_tcp(addr: &[SocketAddr]) -> Result<Listener> {
+    Ok(Listener::Tcp(TcpListener::bind(addr)?))
+  }
+
+  // Bind Unix domain socket and set the mode and the owner of the socket file
+  #[cfg(unix)]
+  pub fn bind_unix(path: &str, mode: Option<u32>, owner: &str) -> Result<Listener> {
+    // Remove the socket file left after the previous start, but not the socket of the running server
+    if let Ok(meta) = symlink_metadata(path) {
+      if meta.file_type().is_socket() {
+        if UnixStream::connect(path).is_ok() {
+          return Err(Error::new(ErrorKind::AddrInUse, path));
+        }
+        remove_file(path)?;
+      }
+    }
+    let listener = Listener::Unix(UnixListener::bind(path)?, path.to_owned(), true);
+    if let Some(mode) = mode {
+      set_permissions(path, Permissions::from_mode(mode))?;
+    }
+    if !owner.is_empty() {
+      let (uid, gid) = Listener::owner(owner)?;
+      chown(path, uid, gid)?;
+    }
+    Ok(listener)
+  }
+
+  // Unix domain sockets are not available on this platform
+  #[cfg(not(unix))]
+  pub fn bind_unix(_path: &str, _mode: Option<u32>, _owner: &str) -> Result<Listener> {
+    Err(Error::new(ErrorKind::Unsupported, "unix domain sockets are not supported on this platform"))
+  }
+
+  // Decode the owner of the socket file. Example: www-data:www-data, 33:33, www-data, :www-data
+  #[cfg(unix)]
+  fn owner(owner: &str) -> Result<(Option<u32>, Option<u32>)> {
+    let mut split = owner.splitn(2, ':');
+    let user = split.next().unwrap_or("").trim();
+    let group = split.next().unwrap_or("").trim();
+    let uid = match user {
+      "" => None,
+      user => match user.parse::<u32>() {
+        Ok(uid) => Some(uid),
+        Err(_) => {
+          let name = CString::new(user).map_err(|_| Error::new(ErrorKind::InvalidInput, owner))?;
+          // Safety: getpwnam is called with a valid C string and the result is checked for NULL
+          let pwd = unsafe { libc::getpwnam(name.as_ptr()) };
+          if pwd.is_null() {
+            return Err(Error::new(ErrorKind::NotFound, format!("unknown user {}", user)));
+          }
+          Some(unsafe { (*pwd).pw_uid })
+        },
+      },
+    };
+    let gid = match group {
+      "" => None,
+      group => match group.parse::<u32>() {
+        Ok(gid) => Some(gid),
+        Err(_) => {
+          let name = CString::new(group).map_err(|_| Error::new(ErrorKind::InvalidInput, owner))?;
+          // Safety: getgrnam is called with a valid C string and the result is checked for NULL
+          let grp = unsafe { libc::getgrnam(name.as_ptr()) };
+          if grp.is_null() {
+            return Err(Error::new(ErrorKind::NotFound, format!("unknown group {}", group)));
+          }
+          Some(unsafe { (*grp).gr_gid })
+        },
+      },
+    };
+    Ok((uid, gid))
+  }
+
+  // Address to connect to the listener, it is used to wake up the blocking accept
+  pub fn local(&self) -> Result<Bind> {
+    match self {
+      Listener::Tcp(l) => {
+        let mut addr = l.local_addr()?;
+        if addr.ip().is_unspecified() {
+          match addr.ip() {
+            IpAddr::V4(_) => addr.set_ip(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+            IpAddr::V6(_) => addr.set_ip(IpAddr::V6(Ipv6Addr::LOCALHOST)),
+          }
+        }
+        Ok(Bind::Tcp(addr))
+      },
+      #[cfg(unix)]
+      Listener::Unix(_, path, _) => Ok(Bind::Unix(path.to_owned())),
+    }
+  }
+
+  // Kind of the socket for the handover to the new server
+  pub fn kind(&self) -> &'static str {
+    match self {
+      Listener::Tcp(_) => "tcp",
+      #[cfg(unix)]
+      Listener::Unix(_, _, true) => "unix",
+      #[cfg(unix)]
+      Listener::Unix(_, _, false) => "unix-systemd",
+    }
+  }
+
+  // Take the socket handed over by the previous server or passed by systemd
+  // The socket file of "unix-systemd" belongs to systemd, it isn't removed at the close
+  #[cfg(unix)]
+  pub fn from_fd(kind: &str, fd: RawFd) -> Result<Listener> {
+    match kind {
+      // Safety: the descriptor is checked by the caller and isn't used anywhere else
+      "tcp" => Ok(Listener::Tcp(unsafe { TcpListener::from_raw_fd(fd) })),
+      "unix" | "unix-systemd" => {
+        let listener = unsafe { UnixListener::from_raw_fd(fd) };
+        let path = match listener.local_addr()?.as_pathname().and_then(|path| path.to_str()) {
+          Some(path) => path.to_owned(),
+          None => return Err(Error::new(ErrorKind::InvalidInput, format!("unix domain socket {} has no path", fd))),
+        };
+        Ok(Listener::Unix(listener, path, kind == "unix"))
+      },
+      _ => Err(Error::new(ErrorKind::InvalidInput, format!("unknown socket {}:{}", kind, fd))),
+    }
+  }
+
+  // Close the socket, but leave the socket file to the new server
+  pub fn release(mut self) {
+    #[cfg(unix)]
+    if let Listener::Unix(_, _, owned) = &mut self {
+      *owned = false;
+    }
+  }
+
+  // Accept a new connection from the WEB server
+  pub fn accept(&self) -> Result<Stream> {
+    match self {
+      Listener::Tcp(l) => Ok(Stream::Tcp(l.accept()?.0)),
+      #[cfg(unix)]
+      Listener::Unix(l, _, _) => Ok(Stream::Unix(l.accept()?.0)),
+    }
+  }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Listener {
+  fn as_raw_fd(&self) -> RawFd {
+    match self {
+      Listener::Tcp(l) => l.as_raw_fd(),
+      Listener::Unix(l, _, _) => l.as_raw_fd(),
+    }
+  }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+  // Remove the socket file
+  fn drop(&mut self) {
+    if let Listener::Unix(_, path, true) = self {
+      remove_file(path).unwrap_or(());
+    }
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::{io::{Read, Write, ErrorKind}, os::unix::net::UnixStream, time::{Duration, Instant}, thread};
+
+  use super::{Stream, Deadline};
+
+  #[test]
+  fn slow_client_is_cut_off_at_deadline() {
+    let (server, mut client) = UnixStream::pair().unwrap();
+    let mut stream = Stream::Unix(server);
+    let start = Instant::now();
+    let writer = thread::spawn(move || {
+      // One byte in 50 ms, each read gets data before its own timeout
+      for _ in 0..20 {
+        if client.write_all(b"x").is_err() {
+          break;
+        }
+        thread::sleep(Duration::from_millis(50));
+      }
+    });
+    let mut buffer = [0; 64];
+    let mut deadline = Deadline::new(&mut stream, start + Duration::from_millis(300));
+    let e = deadline.read_exact(&mut buffer).err().unwrap();
+    assert!(matches!(e.kind(), ErrorKind::TimedOut | ErrorKind::WouldBlock));
+    assert!(start.elapsed() < Duration::from_millis(600));
+    drop(stream);
+    writer.join().unwrap();
+  }
+
+  #[test]
+  fn reading_after_deadline_fails() {
+    let (server, mut client) = UnixStream::pair().unwrap();
+    client.write_all(b"data").unwrap();
+    let mut stream = Stream::Unix(server);
+    let mut buffer = [0; 4];
+    let e = Deadline::new(&mut stream, Instant::now()).read(&mut buffer).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::TimedOut);
+  }
+}
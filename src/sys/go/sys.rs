@@ -1,10 +1,7 @@
-use std::{sync::{Arc, Mutex, RwLock}, cell::RefCell, rc::Rc, collections::HashMap, fs::remove_file};
+use std::{sync::{Arc, Mutex, RwLock}, collections::HashMap, panic::{catch_unwind, AssertUnwindSafe}, any::Any};
 
-use chrono::{Duration, Utc};
-use postgres::Client;
-
-use crate::app::action::{Action, Answer};
-use super::{worker::Worker, i18n::LangItem};
+use crate::{app::action::{Action, Answer}, sys::{init::Init, error::TryteexError}};
+use super::{go::Go, storage::Storage, db::DbLease, worker::Worker, i18n::LangItem, fastcgi::{Role, Output}, stream::Stream};
 
 // Wrapper for the fastCGI server
 pub struct Sys { }
@@ -13,13 +10,16 @@ impl Sys {
   // Constuctor
   pub fn run(
     worker: Arc<Mutex<Worker>>, 
-    sql: Rc<RefCell<Client>>, 
+    sql: DbLease, 
+    role: Role,
     param: &HashMap<String, String>, 
     stdin: &Option<Vec<u8>>, 
+    data: &Option<Vec<u8>>, 
     i18n: &HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>>,
     langs: &Vec<LangItem>,
     tpls: &HashMap<String, HashMap<String, HashMap<String, String>>>,
-  ) -> Vec<u8> {
+    output: Arc<Mutex<Output<Stream>>>,
+  ) {
     let storage;
     let salt;
     let dir;
@@ -40,46 +40,119 @@ impl Sys {
       salt = i.salt.clone();
       dir = i.dir.clone();
     }
-    // Run CRM
-    let mut action = Action::new(sql, salt, storage, param, stdin, dir, i18n, langs, tpls);
-    let text = match action.start() {
-      // Answer::Raw(answer) => answer,
-      Answer::String(answer) => answer.into_bytes(),
-      Answer::None => Vec::new(),
-    }; 
-    action.stop(); 
-    // Prepare answer to the WEB server
-    let mut answer: Vec<String> = Vec::with_capacity(16);
-    answer.push("HTTP/1.1 ".to_owned());
-
-    if let Some(location) = action.redirect_get() {
-      if location.permanently {
-        answer.push(format!("{}\r\n", Action::http_code_get(301)));
-      } else {
-        answer.push(format!("{}\r\n", Action::http_code_get(302)));
+    let storage_panic = Arc::clone(&storage);
+    // A panic of the controller is turned into the 500 page, the worker keeps working
+    let result = catch_unwind(AssertUnwindSafe(|| {
+      // Run CRM
+      let mut action = Action::new(sql, salt, storage, param, stdin, dir, i18n, langs, tpls, Arc::clone(&output));
+      // The database isn't available to load the session
+      if !action.db_available() {
+        Mutex::lock(&output).unwrap().stdout(Sys::page(503, "").as_bytes());
+        return;
+      }
+      // FastCGI Authorizer only checks access to the requested route
+      if let Role::Authorized = role {
+        let answer = Sys::authorize(&mut action);
+        action.stop();
+        Mutex::lock(&output).unwrap().stdout(&answer);
+        return;
+      }
+      let text = match role {
+        // FastCGI Filter runs the filter controller set by the WEB server
+        Role::Filter => {
+          action.filter = data.as_deref();
+          let route = match param.get("TRYTEEX_FILTER") {
+            Some(route) => route.as_str(),
+            None => "/index/index/filter",
+          };
+          match action.filter(route) {
+            Answer::String(answer) => answer.into_bytes(),
+            // Without the filter the data is returned unchanged
+            Answer::None => data.clone().unwrap_or_default(),
+          }
+        },
+        _ => match action.start() {
+          // Answer::Raw(answer) => answer,
+          Answer::String(answer) => answer.into_bytes(),
+          Answer::None => Vec::new(),
+        },
+      }; 
+      action.stop(); 
+      // Prepare answer to the WEB server
+      let mut output = Mutex::lock(&output).unwrap();
+      if output.sent {
+        // The controller has already sent the headers and a part of the answer
+        if !text.is_empty() {
+          output.stdout(&text);
+        }
+      } else if output.stdout(action.header(Some(text.len())).as_bytes()) && !text.is_empty() {
+        output.stdout(&text);
       }
-      answer.push(format!("{}\r\n", location.url));
-    } else if let Some(code) = action.http_code {
-      answer.push(format!("{}\r\n", Action::http_code_get(code)));
-    } else {
-      answer.push(format!("{}\r\n", Action::http_code_get(200)));
+    }));
+    if let Err(e) = result {
+      Sys::recover(e, &go, &init, &storage_panic, &output, param);
     }
-    let time = Utc::now() + Duration::seconds(action.set_cookie.time.into());
-    let date: String = time.format("%a, %d-%b-%Y %H:%M:%S GMT").to_string();
-    answer.push(format!("Set-Cookie: {}={}; Expires={}; Max-Age={}; path=/; domain={}; Secure; SameSite=none\r\n", action.set_cookie.key, action.set_cookie.value, date, action.set_cookie.time, action.host));
-    answer.push("Connection: keep-alive\r\n".to_owned());
-    answer.push("Content-Type: text/html; charset=utf-8\r\n".to_owned());
-    answer.push(format!("Content-Length: {}\r\n", text.len()));
-    answer.push("\r\n".to_owned());
+  }
 
-    let mut answer = answer.join("").into_bytes();
-    answer.extend_from_slice(&text[..]);
-    // delete temp files
-    for (_, val) in &action.file {
-      for f in val {
-        remove_file(&f.tmp).unwrap_or_default();
-      }
+  // Clean up after the panic of the controller and answer with the 500 page
+  fn recover(
+    e: Box<dyn Any + Send>, 
+    go: &Arc<Mutex<Go>>, 
+    init: &Arc<RwLock<Init>>, 
+    storage: &Arc<Mutex<Storage>>, 
+    output: &Arc<Mutex<Output<Stream>>>, 
+    param: &HashMap<String, String>,
+  ) {
+    let text = match e.downcast_ref::<&str>() {
+      Some(text) => text.to_string(),
+      None => match e.downcast_ref::<String>() {
+        Some(text) => text.to_owned(),
+        None => "".to_owned(),
+      },
+    };
+    let url = match param.get("REDIRECT_URL") {
+      Some(url) => url.as_str(),
+      None => "",
+    };
+    {
+      let g = Mutex::lock(go).unwrap();
+      let log = RwLock::read(&g.log).unwrap();
+      log.write_err(&TryteexError::Server(506, format!("{} {}", url, text)));
     }
-    answer
+    // The locks may be left by the controller, the transaction is rolled back when the connection returns to the pool
+    storage.clear_poison();
+    output.clear_poison();
+    let mut output = Mutex::lock(output).unwrap();
+    if !output.sent {
+      let i = RwLock::read(init).unwrap();
+      output.stdout(Sys::page(500, &i.sys.error_page).as_bytes());
+    }
+  }
+
+  // Answer with the error page, the short built-in page is used if the body is empty
+  pub fn page(code: u16, body: &str) -> String {
+    let status = Action::http_code_get(code);
+    let body = match body {
+      "" => format!("<html><body><h1>{}</h1></body></html>", status),
+      body => body.to_owned(),
+    };
+    format!("HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\r\n{}", status, body.len(), body)
+  }
+
+  // Answer of the FastCGI Authorizer
+  fn authorize(action: &mut Action) -> Vec<u8> {
+    let code = action.authorize();
+    let mut answer: Vec<String> = Vec::with_capacity(8);
+    answer.push(format!("HTTP/1.1 {}\r\n", Action::http_code_get(code)));
+    if code == 200 {
+      // The WEB server can pass these values to the protected upstream
+      answer.push(format!("Variable-USER_ID: {}\r\n", action.user_id));
+      answer.push(format!("Variable-ROLE_ID: {}\r\n", action.role_id));
+      answer.push(format!("Variable-SESSION_ID: {}\r\n", action.session_id));
+      answer.push(format!("Variable-LANG_ID: {}\r\n", action.lang_id));
+    }
+    answer.push("Content-Length: 0\r\n".to_owned());
+    answer.push("\r\n".to_owned());
+    answer.join("").into_bytes()
   }
-}
\ No newline at end of file
+}
@@ -0,0 +1,231 @@
+#[cfg(unix)]
+use std::{env, mem, process, os::unix::{io::RawFd, net::UnixDatagram}};
+use std::time::Duration;
+
+#[cfg(unix)]
+use super::handover::Handover;
+use crate::sys::error::TryteexError;
+
+use super::stream::Listener;
+
+// The first descriptor passed by systemd
+#[cfg(unix)]
+const LISTEN_FDS_START: RawFd = 3;
+
+// Sockets passed by systemd at the socket activation
+// The socket named "irc" (FileDescriptorName=irc) is used for IRC, others are used to listen to the WEB server
+pub struct Systemd {
+  pub irc: Option<Listener>,                // IRC socket
+  pub listeners: Vec<Listener>,             // Sockets to listen to the WEB server
+}
+
+#[cfg(unix)]
+impl Systemd {
+  // Take the sockets passed by systemd, None - the sockets aren't passed to this process
+  pub fn listen() -> Result<Option<Systemd>, TryteexError> {
+    let pid = env::var("LISTEN_PID").ok();
+    let count = env::var("LISTEN_FDS").ok();
+    let names = env::var("LISTEN_FDNAMES").unwrap_or_default();
+    // The sockets aren't passed to the next server by accident
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+    let count = match (pid, count) {
+      (Some(pid), Some(count)) if pid == process::id().to_string() => match count.parse::<RawFd>() {
+        Ok(count) if count > 0 => count,
+        _ => return Err(TryteexError::Server(406, format!("LISTEN_FDS={}", count))),
+      },
+      _ => return Ok(None),
+    };
+    let mut names = names.split(':');
+    let mut systemd = Systemd {
+      irc: None,
+      listeners: Vec::new(),
+    };
+    for fd in LISTEN_FDS_START..LISTEN_FDS_START + count {
+      let name = names.next().unwrap_or("");
+      if let Err(e) = Handover::cloexec(fd, true) {
+        return Err(TryteexError::Io(406, format!("{} {}", name, fd), e));
+      }
+      let kind = match Systemd::family(fd) {
+        Some(libc::AF_UNIX) => "unix-systemd",
+        Some(libc::AF_INET) | Some(libc::AF_INET6) => "tcp",
+        _ => return Err(TryteexError::Server(406, format!("{} {} isn't a TCP or unix domain socket", name, fd))),
+      };
+      match Listener::from_fd(kind, fd) {
+        Ok(listener) if name == "irc" => systemd.irc = Some(listener),
+        Ok(listener) => systemd.listeners.push(listener),
+        Err(e) => return Err(TryteexError::Io(406, format!("{} {}", name, fd), e)),
+      }
+    }
+    if systemd.listeners.is_empty() {
+      return Err(TryteexError::Server(406, "no sockets to listen to the WEB server".to_owned()));
+    }
+    Ok(Some(systemd))
+  }
+
+  // Send the state of the server to systemd, for example "READY=1"
+  // The notification is lost without an error if the server isn't started by systemd
+  pub fn notify(state: &str) {
+    let path = match env::var("NOTIFY_SOCKET") {
+      Ok(path) if !path.is_empty() => path,
+      _ => return,
+    };
+    let socket = match UnixDatagram::unbound() {
+      Ok(socket) => socket,
+      Err(_) => return,
+    };
+    // The socket in the abstract namespace starts with "@"
+    #[cfg(target_os = "linux")]
+    if let Some(name) = path.strip_prefix('@') {
+      use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+      if let Ok(addr) = SocketAddr::from_abstract_name(name) {
+        socket.send_to_addr(state.as_bytes(), &addr).unwrap_or(0);
+      }
+      return;
+    }
+    socket.send_to(state.as_bytes(), &path).unwrap_or(0);
+  }
+
+  // Interval to send "WATCHDOG=1", None - the watchdog of systemd is off
+  // The server started at the restart takes the watchdog of the previous one
+  pub fn watchdog(handover: bool) -> Option<Duration> {
+    let usec = env::var("WATCHDOG_USEC").ok()?.parse::<u64>().ok()?;
+    if let Ok(pid) = env::var("WATCHDOG_PID") {
+      if !handover && pid != process::id().to_string() {
+        return None;
+      }
+    }
+    match usec {
+      0 => None,
+      usec => Some(Duration::from_micros(usec / 2)),
+    }
+  }
+
+  // Address family of the socket
+  fn family(fd: RawFd) -> Option<libc::c_int> {
+    // Safety: getsockname writes no more than "len" bytes to the zeroed address
+    unsafe {
+      let mut addr: libc::sockaddr_storage = mem::zeroed();
+      let mut len = mem::size_of::<libc::sockaddr_storage>() as libc::socklen_t;
+      match libc::getsockname(fd, &mut addr as *mut _ as *mut libc::sockaddr, &mut len) {
+        0 => Some(libc::c_int::from(addr.ss_family)),
+        _ => None,
+      }
+    }
+  }
+}
+
+#[cfg(not(unix))]
+impl Systemd {
+  // Systemd isn't available on this platform
+  pub fn listen() -> Result<Option<Systemd>, TryteexError> {
+    Ok(None)
+  }
+
+  // Nothing to notify
+  pub fn notify(_state: &str) {}
+
+  // The watchdog of systemd is off
+  pub fn watchdog(_handover: bool) -> Option<Duration> {
+    None
+  }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+  use std::{env, process, time::Duration, os::unix::net::UnixDatagram};
+
+  use crate::sys::test;
+
+  use super::Systemd;
+
+  // Receive one notification
+  fn recv(socket: &UnixDatagram) -> String {
+    let mut buffer = [0; 256];
+    let len = socket.recv(&mut buffer).unwrap();
+    String::from_utf8_lossy(&buffer[..len]).into_owned()
+  }
+
+  #[test]
+  fn notify_sends_states() {
+    let _env = test::env();
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("notify.sock");
+    let socket = UnixDatagram::bind(&path).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    env::set_var("NOTIFY_SOCKET", &path);
+    let ready = format!("READY=1\nMAINPID={}", process::id());
+    Systemd::notify(&ready);
+    Systemd::notify("WATCHDOG=1");
+    Systemd::notify("STOPPING=1");
+    env::remove_var("NOTIFY_SOCKET");
+    assert_eq!(recv(&socket), ready);
+    assert_eq!(recv(&socket), "WATCHDOG=1");
+    assert_eq!(recv(&socket), "STOPPING=1");
+  }
+
+  #[cfg(target_os = "linux")]
+  #[test]
+  fn notify_abstract_socket() {
+    use std::os::{linux::net::SocketAddrExt, unix::net::SocketAddr};
+    let _env = test::env();
+    let name = format!("tryteex-test-{}", process::id());
+    let socket = UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+    socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    env::set_var("NOTIFY_SOCKET", format!("@{}", name));
+    Systemd::notify("READY=1");
+    env::remove_var("NOTIFY_SOCKET");
+    assert_eq!(recv(&socket), "READY=1");
+  }
+
+  #[test]
+  fn listen_without_sockets() {
+    let _env = test::env();
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    assert!(matches!(Systemd::listen(), Ok(None)));
+  }
+
+  #[test]
+  fn listen_sockets_of_another_process() {
+    let _env = test::env();
+    env::set_var("LISTEN_PID", (process::id() + 1).to_string());
+    env::set_var("LISTEN_FDS", "2");
+    env::set_var("LISTEN_FDNAMES", "web:irc");
+    assert!(matches!(Systemd::listen(), Ok(None)));
+    // The variables aren't passed to the next server
+    assert!(env::var("LISTEN_PID").is_err());
+    assert!(env::var("LISTEN_FDS").is_err());
+    assert!(env::var("LISTEN_FDNAMES").is_err());
+  }
+
+  #[test]
+  fn listen_wrong_count() {
+    let _env = test::env();
+    for count in ["0", "-1", "x"] {
+      env::set_var("LISTEN_PID", process::id().to_string());
+      env::set_var("LISTEN_FDS", count);
+      match Systemd::listen() {
+        Err(e) => assert_eq!(e.code(), 406),
+        Ok(_) => panic!("LISTEN_FDS={} is accepted", count),
+      }
+    }
+  }
+
+  #[test]
+  fn watchdog_interval() {
+    let _env = test::env();
+    env::set_var("WATCHDOG_USEC", "2000000");
+    env::remove_var("WATCHDOG_PID");
+    assert_eq!(Systemd::watchdog(false), Some(Duration::from_secs(1)));
+    // The watchdog of another process, but the new server at the restart takes it
+    env::set_var("WATCHDOG_PID", (process::id() + 1).to_string());
+    assert_eq!(Systemd::watchdog(false), None);
+    assert_eq!(Systemd::watchdog(true), Some(Duration::from_secs(1)));
+    env::set_var("WATCHDOG_USEC", "0");
+    assert_eq!(Systemd::watchdog(true), None);
+    env::remove_var("WATCHDOG_USEC");
+    env::remove_var("WATCHDOG_PID");
+  }
+}
@@ -1,21 +1,21 @@
-use std::{collections::HashMap, io::Error, fs::{read_dir, read_to_string}};
+use std::{collections::HashMap, fs::{read_dir, read_to_string}};
+
+use crate::sys::error::TryteexError;
 
 // Templates system
 pub struct Template {
-  pub load: bool,                                                   // Template loaded
   pub tpls: HashMap<String, HashMap<String, HashMap<String, String>>>,  // List of templates
 }
 
 impl Template {
   pub fn new() -> Template {
     Template {
-      load: false,
       tpls: HashMap::with_capacity(32),
     }
   }
 
   // Load templates
-  pub fn load_templates(&mut self, dir: &str) -> Result<(), Error> {
+  pub fn load_templates(&mut self, dir: &str) -> Result<(), TryteexError> {
     // Read dir with application data
     match read_dir(dir) {
       Ok(d1) => {
@@ -60,7 +60,7 @@ impl Template {
                                                         Ok(t) => {
                                                           c.insert(view.to_owned(), t);
                                                         },
-                                                        Err(e) => return Err(e),
+                                                        Err(e) => return Err(TryteexError::Io(380, file, e)),
                                                       }
                                                     }
                                                   },
@@ -68,33 +68,33 @@ impl Template {
                                                 }
                                               }
                                             },
-                                            Err(e) => return Err(e),
+                                            Err(e) => return Err(TryteexError::Io(380, "".to_owned(), e)),
                                           }
                                         }
                                       },
-                                      Err(e) => return Err(e),
+                                      Err(e) => return Err(TryteexError::Io(380, p2.path().to_string_lossy().into_owned(), e)),
                                     }
                                   },
                                   None => {},
                                 }
                               }
                             },
-                            Err(e) => return Err(e),
+                            Err(e) => return Err(TryteexError::Io(380, "".to_owned(), e)),
                           }
                         }
                       },
-                      Err(e) => return Err(e),
+                      Err(e) => return Err(TryteexError::Io(380, p1.path().to_string_lossy().into_owned(), e)),
                     }
                   },
                   None => {},
                 }
               }
             },
-            Err(e) => return Err(e),
+            Err(e) => return Err(TryteexError::Io(380, "".to_owned(), e)),
           }
         }
       },
-      Err(e) => return Err(e),
+      Err(e) => return Err(TryteexError::Io(380, dir.to_owned(), e)),
     };
     Ok(())
   }
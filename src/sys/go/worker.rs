@@ -1,250 +1,186 @@
-use std::{thread, sync::{Arc, Mutex, mpsc, RwLock, MutexGuard}, net::TcpStream, collections::HashMap, cell::RefCell, rc::Rc};
+use std::{thread, sync::{Arc, Mutex, mpsc::{self, RecvTimeoutError}, RwLock, atomic::{AtomicUsize, Ordering}}, net::Shutdown, collections::HashMap, time::{Duration, Instant}};
 
-use postgres::{Client, NoTls};
-use postgres_protocol::escape::escape_literal;
-use cast::u8;
+use postgres::CancelToken;
 
-use crate::sys::log::LogApp;
+use crate::sys::error::TryteexError;
 
-use super::{go::Go, fastcgi::{Record, FASTCGI_MAX_REQUEST_LEN, FastCGI, RecordType, HeaderType, ContentData}, sys::Sys, i18n::{LangItem, I18n}, template::Template};
+use super::{go::Go, idle::Idle, db::{DbPool, DbLease}, fastcgi::{Record, FASTCGI_MAX_REQUEST_LEN, FASTCGI_KEEP_CONN, FastCGI, RecordType, HeaderType, ContentData, Role, Output}, sys::Sys, snapshot::Snapshot, stream::Stream};
 // Message to threads
 pub enum Message {
   Terminate,          // Stop all threads
-  Job(TcpStream),     // Accept fastCGI connection from the WEB server
+  Job(Stream),        // Accept fastCGI connection from the WEB server
+  Request(Job),       // Run one completed request of a multiplexed connection
+}
+
+// Completed fastCGI request, ready to start the CRM system
+pub struct Job {
+  pub begin: Record,                            // "Begin" record
+  pub param: HashMap<String, String>,           // Params
+  pub stdin: Option<Vec<u8>>,                   // Stdin
+  pub data: Option<Vec<u8>>,                    // Data of the FastCGI Filter
+  pub writer: Arc<Mutex<Stream>>,               // Shared writing half of the connection
+  pub pending: Arc<AtomicUsize>,                // Requests of the connection which aren't answered yet
+  pub close: bool,                              // Close the connection after the answer
+}
+
+// State of one fastCGI request on the connection
+struct Request {
+  begin: Record,                                // "Begin" record
+  param: HashMap<String, String>,               // Params
+  stdin: Option<Vec<u8>>,                       // Stdin
+  data: Option<Vec<u8>>,                        // Data of the FastCGI Filter
+  status: Status,                               // Status of the request
+  start: Instant,                               // Time of the "Begin" record
 }
 
 // Status of the fastCGI connection
-#[derive(PartialEq)]
+#[derive(PartialEq, Debug)]
 pub enum Status {
   None,               // Nothing or Init
   Begin,              // Receive a "Begin" request
   Param,              // Receive a "Param" request
   ParamEnd,           // Receive a empty "Param" request
   Stdin,              // Receive a "Stdin" request
+  Data,               // Receive a empty "Stdin" request and wait a "Data" request of the FastCGI Filter
   Work,               // Receive a empty "Stdin" request and start CRM system
   End,                // Finish
 } 
 
+// Deadlines of the fastCGI connection
+#[derive(Clone, Copy)]
+pub struct Timeout {
+  pub keep_alive: Duration,                     // Idle time of the keep-alive connection
+  pub read: Duration,                           // Time to receive the whole request
+  pub handler: Duration,                        // Time to run the controller
+  pub write: Duration,                          // Time to write one part of the answer
+}
+
 // Worker of thread
 pub struct Worker {
   pub id: usize,                                // Index of worker
   pub go: Arc<Mutex<Go>>,                       // Main struct
+  pub idle: Arc<Idle>,                          // Free workers
   pub start: bool,                              // Worker is started
   pub stop: bool,                               // Send the "stop" signal
   pub thread: Option<thread::JoinHandle<()>>,   // Thread 
   pub status: Status,                           // Status for the Worker
   pub count: usize,                             // Number of completed request
+  pub total: usize,                             // Number of completed request since the start of the worker
+  pub timeout: Timeout,                         // Deadlines of the connection
+  pub deadline: Option<Instant>,                // Deadline of the running controller
+  pub output: Option<Arc<Mutex<Output<Stream>>>>, // Answer of the running controller
+  pub pool: Arc<DbPool>,                        // Connections to the database
+  pub cancel: Arc<Mutex<Option<CancelToken>>>,  // Cancel the running SQL query
+  pub recycle: bool,                            // The worker is hung and replaced by a new one
 }
 
 impl Worker {
   // Constructor
-  pub fn new(id:usize, go: Arc<Mutex<Go>>, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Arc<Mutex<Worker>> {
-    let conn;
+  pub fn new(id:usize, go: Arc<Mutex<Go>>, receiver: Arc<Mutex<mpsc::Receiver<Message>>>) -> Result<Arc<Mutex<Worker>>, TryteexError> {
+    let pool;
     let max_connection: usize;
-    let tz;
+    let timeout: Timeout;
+    let idle_timeout: Duration;
     // Init variables
     {
       let g = Mutex::lock(&go).unwrap();
       let init = RwLock::read(&g.init).unwrap();
+      pool = Arc::clone(&g.db);
       max_connection = init.sys.max_connection.into();
-      let db = &init.db;
-      tz = format!("SET timezone TO {};", escape_literal(&init.time_zone));
-      conn = format!("host='{}' port='{}' dbname='{}' user='{}' password='{}' connect_timeout=2 application_name='{} {}' options='--client_encoding=UTF8'", db.host, &db.port, &db.name, &db.user, &db.pwd, &env!("CARGO_PKG_NAME"), &env!("CARGO_PKG_VERSION"));
-    }
-    // Connect to the database
-    let mut sql = match Client::connect(&conn, NoTls) {
-        Ok(sql) => sql,
-        Err(e) => {
-          let go = Mutex::lock(&go).unwrap();
-          let log = RwLock::read(&go.log).unwrap();
-          log.exit_err(&LogApp::get_error(350, &e.to_string()));
-        },
-    };
-    
-    // Set timezone
-    if tz.len() > 0 {
-      if let Err(e) = sql.query(&tz, &[]) {
-        let go = Mutex::lock(&go).unwrap();
-        let log = RwLock::read(&go.log).unwrap();
-        log.exit_err(&LogApp::get_error(351, &e.to_string()));
+      timeout = Timeout {
+        keep_alive: Duration::from_secs(init.sys.keep_alive.into()),
+        read: Duration::from_secs(init.sys.read_timeout.into()),
+        handler: Duration::from_secs(init.sys.handler_timeout.into()),
+        write: Duration::from_secs(init.sys.write_timeout.into()),
       };
+      idle_timeout = Duration::from_secs(init.sys.idle_timeout.into());
     }
-    // Load enable languages and translates
+    let cancel = Arc::new(Mutex::new(None));
+    let go_panic = Arc::clone(&go);
+    let go_thread = Arc::clone(&go);
+    let idle;
     {
-      // Reading data will take place in one thread, others will wait.
-      // When the data is read, other threads will not re-read it.
       let g = Mutex::lock(&go).unwrap();
-      let init = RwLock::read(&g.init).unwrap();
-      let mut i18n = Mutex::lock(&g.i18n).unwrap();
-      // Indication of read data
-      if !i18n.load {
-        // Load enable languages
-        let text = "SELECT lang_id, lang, code, name FROM lang WHERE enable ORDER BY sort";
-        match sql.query(text, &[]) {
-          Ok(res) => {
-            for row in res {
-              let lang_id: i64 = row.get(0);
-              let lang_code: String = row.get(1);
-              let code: String = row.get(2);
-              let name: String = row.get(3);
-              let lang_id = u8(lang_id).unwrap();
-              i18n.langs_code.insert(lang_code.clone(), lang_id);
-              let l = LangItem {lang_id, code, lang: lang_code, name, };
-              i18n.langs.push(l.clone());
-            }
-          },
-          Err(e) => {
-            let log = RwLock::read(&g.log).unwrap();
-            log.exit_err(&LogApp::get_error(351, &e.to_string()));
-          },
-        };
-        // Read translates
-        if let Err(e) = i18n.load_lang(&init.dir) {
-          let log = RwLock::read(&g.log).unwrap();
-          log.exit_err(&LogApp::get_error(370, &e.to_string()));
-        };
-        // Set indication of read data
-        i18n.load = true;
-      }
-
-      let mut tpl = Mutex::lock(&g.tpl).unwrap();
-      if !tpl.load {
-        // Load templates
-        if let Err(e) = tpl.load_templates(&init.dir) {
-          let log = RwLock::read(&g.log).unwrap();
-          log.exit_err(&LogApp::get_error(380, &e.to_string()));
-        };
-      }
+      idle = Arc::clone(&g.idle);
     }
-    let go_panic = Arc::clone(&go);
-    let go_thread = Arc::clone(&go);
     // Init Worker
     let worker = Worker {
       id,
       go,
+      idle,
       start: false,
       stop: false,
       thread: None,
       status: Status::None,
       count: 0,
+      total: 0,
+      timeout,
+      deadline: None,
+      output: None,
+      pool,
+      cancel,
+      recycle: false,
     };
     let worker = Arc::new(Mutex::new(worker));
     let worker_thread = Arc::clone(&worker);
     // Start the thread
-    let thread = thread::spawn(move || {
-      let sql = Rc::new(RefCell::new(sql));
-      let i18n: HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>>;
-      let langs: Vec<LangItem>;
-      let tpls: HashMap<String, HashMap<String, HashMap<String, String>>>;
-      // Init variable for translations
-      let i118n_th;
-      let tpl_th;
+    let thread = thread::Builder::new().spawn(move || {
+      // Translations and templates, each request takes the current snapshot
+      let snapshot;
       {
         let g = Mutex::lock(&go_thread).unwrap();
-        i118n_th = Arc::clone(&g.i18n);
-        tpl_th = Arc::clone(&g.tpl);
-      }
-      {
-        let lang_lock = Mutex::lock(&i118n_th).unwrap();
-        langs = lang_lock.clone_lang();
-        i18n = Worker::prepare_lang(lang_lock);
-      }
-      {
-        let tpl_lock = Mutex::lock(&tpl_th).unwrap();
-        tpls = Worker::prepare_tpl(tpl_lock);
+        snapshot = Arc::clone(&g.snapshot);
       }
 
       // Start the thread in an endless cycle
       loop {
-        let mut begin_record: Option<Record> = None;
-        let mut param_record: HashMap<String, String> = HashMap::with_capacity(128);
-        let mut stdin_record: Option<Vec<u8>> = None;
         let wait = Mutex::lock(&receiver).unwrap();
         // Waiting to receive a WEB server connection signal
-        match wait.recv() {
+        match wait.recv_timeout(idle_timeout) {
           // WEB server is connected
           // Check message to thread
           Ok(message) => match message {
             Message::Job(stream) => {
+              Worker::take(&worker_thread);
               // Run fastcgi connection
               Worker::fastcgi_connection(
                 Arc::clone(&worker_thread), 
                 stream, max_connection, 
-                Rc::clone(&sql), 
-                &mut begin_record, 
-                &mut param_record, 
-                &mut stdin_record,
-                &i18n,
-                &langs,
-                &tpls,
+                &snapshot,
               );
-              let go;
-              {
-                let mut w = Mutex::lock(&worker_thread).unwrap();
-                w.start = false;
-                w.status = Status::None;
-                go = Arc::clone(&w.go);
+              if !Worker::free(&worker_thread) {
+                break;
               }
-              {
-                let mut g = Mutex::lock(&go).unwrap();
-                g.use_connection -= 1;
+            },
+            Message::Request(job) => {
+              // Run the request handed over from a multiplexed connection
+              Worker::take(&worker_thread);
+              Worker::run_job(Arc::clone(&worker_thread), job, &snapshot);
+              if !Worker::free(&worker_thread) {
+                break;
               }
             },
             Message::Terminate => break,
           },
+          // The extra worker is stopped after the idle timeout
+          Err(RecvTimeoutError::Timeout) => if Go::retire(&go_thread, id) {
+            break;
+          },
           Err(e) => {
             let go = Mutex::lock(&go_panic).unwrap();
             let log = RwLock::read(&go.log).unwrap();
-            log.exit_err(&LogApp::get_error(500, &e.to_string()));
+            log.exit_err(&TryteexError::Server(500, e.to_string()));
           },
         }
       };
-    });
+    }).map_err(|e| TryteexError::Io(503, "".to_owned(), e))?;
     {
       let mut w = Mutex::lock(&worker).unwrap();
       w.thread = Some(thread);
     }
-    worker
+    Ok(worker)
   }
   
-  // Copy lang to each thread
-  fn prepare_lang(i18n: MutexGuard<I18n>) -> HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>> {
-    let mut data: HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>> = HashMap::with_capacity(i18n.data.len());
-    for (lang_id, modules) in &i18n.data {
-      let mut vl: HashMap<String, HashMap<String, HashMap<String, String>>> = HashMap::with_capacity(modules.len());
-      for (module, classes) in modules {
-        let mut v: HashMap<String, HashMap<String, String>> = HashMap::with_capacity(classes.len());
-        for (class, map) in classes {
-          let mut m: HashMap<String, String> = HashMap::with_capacity(map.len());
-          for (key, val) in map {
-            m.insert(key.to_owned(), val.to_owned());
-          }
-          v.insert(class.to_owned(), m);
-        }
-        vl.insert(module.to_owned(), v);
-      }
-      data.insert(*lang_id, vl);
-    }
-    data
-  }
-
-  // Copy lang to each thread
-  fn prepare_tpl(tpl: MutexGuard<Template>) -> HashMap<String, HashMap<String, HashMap<String, String>>> {
-    let mut data: HashMap<String, HashMap<String, HashMap<String, String>>> = HashMap::with_capacity(tpl.tpls.len());
-    for (module, classes) in &tpl.tpls {
-      let mut v: HashMap<String, HashMap<String, String>> = HashMap::with_capacity(classes.len());
-      for (class, map) in classes {
-        let mut m: HashMap<String, String> = HashMap::with_capacity(map.len());
-        for (key, val) in map {
-          m.insert(key.to_owned(), val.to_owned());
-        }
-        v.insert(class.to_owned(), m);
-      }
-      data.insert(module.to_owned(), v);
-    }
-    data
-  }
-
   // Wait terminating of thread
   pub fn join(worker: Arc<Mutex<Worker>>) {
     let thread;
@@ -258,139 +194,305 @@ impl Worker {
     }
   }
 
+  // The thread of the worker is finished
+  pub fn is_finished(worker: &Arc<Mutex<Worker>>) -> bool {
+    let w = Mutex::lock(worker).unwrap();
+    match &w.thread {
+      Some(thread) => thread.is_finished(),
+      None => true,
+    }
+  }
+
+  // Mark the worker as started
+  fn take(worker: &Arc<Mutex<Worker>>) {
+    let mut w = Mutex::lock(worker).unwrap();
+    w.start = true;
+    w.count = 0;
+  }
+
+  // Release the worker after the job
+  // Returns false if the worker has been recycled and must stop
+  fn free(worker: &Arc<Mutex<Worker>>) -> bool {
+    let mut w = Mutex::lock(worker).unwrap();
+    w.start = false;
+    w.status = Status::None;
+    if w.recycle {
+      return false;
+    }
+    w.idle.push(w.id);
+    true
+  }
+
+  // Set the status of the worker
+  fn set_status(worker: &Arc<Mutex<Worker>>, status: Status) {
+    let mut w = Mutex::lock(worker).unwrap();
+    w.status = status;
+  }
+
+  // Run the request handed over from a multiplexed connection
+  fn run_job(
+    worker: Arc<Mutex<Worker>>, 
+    job: Job, 
+    snapshot: &Mutex<Arc<Snapshot>>,
+  ) {
+    let role = FastCGI::role(&job.begin);
+    let output = Arc::new(Mutex::new(Output::new(Arc::clone(&job.writer), job.begin.header.request_id)));
+    let sql;
+    {
+      // The watchdog answers instead of the controller after the deadline
+      let mut w = Mutex::lock(&worker).unwrap();
+      w.status = Status::Work;
+      w.deadline = Some(Instant::now() + w.timeout.handler);
+      w.output = Some(Arc::clone(&output));
+      sql = DbLease::new(Arc::clone(&w.pool), Arc::clone(&w.cancel));
+    }
+    // The reload doesn't change translations and templates of the running request
+    let snapshot = Snapshot::current(snapshot);
+    Sys::run(Arc::clone(&worker), sql, role, &job.param, &job.stdin, &job.data, &snapshot.i18n.data, &snapshot.i18n.langs, &snapshot.tpl.tpls, Arc::clone(&output));
+    {
+      let mut w = Mutex::lock(&worker).unwrap();
+      w.count += 1;
+      w.total += 1;
+      w.status = Status::End;  
+      w.deadline = None;
+      w.output = None;
+    }
+    // Finish ansewer to the WEB server
+    let end = Mutex::lock(&output).unwrap().end();
+    // The connection is closed after the last request on it, other requests still write to the shared writer
+    if job.pending.fetch_sub(1, Ordering::SeqCst) == 1 && end && job.close {
+      let writer = Mutex::lock(&job.writer).unwrap();
+      writer.shutdown(Shutdown::Both).unwrap_or(());
+    }
+  }
+
   // Start fastCGI connection
   pub fn fastcgi_connection(
     worker: Arc<Mutex<Worker>>, 
-    mut stream: TcpStream, 
+    mut stream: Stream, 
     max_connection: usize, 
-    sql: Rc<RefCell<Client>>, 
-    begin_record: &mut Option<Record>, 
-    param_record: &mut HashMap<String, String>, 
-    stdin_record: &mut Option<Vec<u8>>,
-    i18n: &HashMap<u8, HashMap<String, HashMap<String, HashMap<String, String>>>>,
-    langs: &Vec<LangItem>,
-    tpls: &HashMap<String, HashMap<String, HashMap<String, String>>>,
+    snapshot: &Mutex<Arc<Snapshot>>,
   ){
     let mut buffer: [u8; FASTCGI_MAX_REQUEST_LEN] = [0; FASTCGI_MAX_REQUEST_LEN];
     let mut seek: usize = 0;
     let mut size: usize = 0;
     let mut need_read = true;
+    // Requests in progress on this connection: request_id -> request
+    let mut requests: HashMap<u16, Request> = HashMap::with_capacity(8);
+    // The WEB server multiplexes requests over this connection
+    let mut mpx = false;
+    // The WEB server asks to keep the connection for the next request
+    let mut keep_conn = false;
+    // Time of the last received record
+    let mut last = Instant::now();
+    let timeout;
+    {
+      let w = Mutex::lock(&worker).unwrap();
+      timeout = w.timeout;
+    }
+    // A short read timeout lets us check the "stop" signal and the deadlines
+    if stream.set_nonblocking(false).is_err() || stream.set_read_timeout(Some(Duration::from_secs(1))).is_err() || stream.set_write_timeout(Some(timeout.write)).is_err() {
+      return;
+    }
+    // All answers go through one writer, so records of different requests don't mix
+    let writer = match stream.try_clone() {
+      Ok(writer) => Arc::new(Mutex::new(writer)),
+      Err(_) => return,
+    };
+    // Requests from the "Begin" record to the end of the answer, including ones handed over to other workers
+    let pending = Arc::new(AtomicUsize::new(0));
     // Read data from the WEB server in the loop
     loop {
       // Check stop command
       {
+        // After the "stop" signal the requests in progress are finished first
         let w = Mutex::lock(&worker).unwrap();
-        if w.stop {
+        if (w.stop && requests.is_empty()) || w.recycle {
           break;
         }
       }
+      // The WEB server is too slow to send the request
+      if requests.values().any(|request| request.start.elapsed() >= timeout.read) {
+        let go;
+        let id;
+        {
+          let w = Mutex::lock(&worker).unwrap();
+          go = Arc::clone(&w.go);
+          id = w.id;
+        }
+        let g = Mutex::lock(&go).unwrap();
+        let log = RwLock::read(&g.log).unwrap();
+        log.write_err(&TryteexError::Server(505, id.to_string()));
+        break;
+      }
       // Read one command from the WEB server
-      let record = match FastCGI::read_record(&mut seek, &mut size, &mut need_read, &mut buffer[..], &mut stream, max_connection) {
+      let record = match FastCGI::read_record(&mut seek, &mut size, &mut need_read, &mut buffer[..], &mut stream, &writer, max_connection) {
         RecordType::None => continue,
         RecordType::Some(record) => record,
         RecordType::ErrorStream | RecordType::StreamClosed => break,
+        RecordType::Timeout => {
+          // Close the idle connection
+          if requests.is_empty() && last.elapsed() >= timeout.keep_alive {
+            break;
+          }
+          continue;
+        },
       };
+      last = Instant::now();
+      let request_id = record.header.request_id;
+      // The request has been received completely
+      let mut ready = false;
       // This command must go in a certain order
       match record.header.header_type {
         HeaderType::BeginRequest => {
           // Got "Begin" record
-          *begin_record = Some(record);
-          let mut w = Mutex::lock(&worker).unwrap();
-          if Status::None != w.status {
+          if requests.contains_key(&request_id) {
             break;
           }
-          w.status = Status::Begin;
-
+          if let ContentData::BeginRequest(begin) = &record.data {
+            keep_conn = begin.flags & FASTCGI_KEEP_CONN == FASTCGI_KEEP_CONN;
+          }
+          requests.insert(request_id, Request {
+            begin: record,
+            param: HashMap::with_capacity(128),
+            stdin: None,
+            data: None,
+            status: Status::Begin,
+            start: Instant::now(),
+          });
+          pending.fetch_add(1, Ordering::SeqCst);
+          if requests.len() > 1 {
+            mpx = true;
+          }
+          Worker::set_status(&worker, Status::Begin);
         },
         HeaderType::AbortRequest => {
           // Got "Abort" record
-          if let Some(record) = begin_record {
-            FastCGI::write_abort(&record.header, &mut stream).unwrap_or(());
+          if let Some(request) = requests.remove(&request_id) {
+            pending.fetch_sub(1, Ordering::SeqCst);
+            let mut writer = Mutex::lock(&writer).unwrap();
+            FastCGI::write_abort(&request.begin.header, &mut *writer).unwrap_or(());
+          }
+          if !keep_conn && requests.is_empty() {
+            break;
           }
-          break;
         },
         HeaderType::Params => {
           // Got "Param" record
-          {
-            let w = Mutex::lock(&worker).unwrap();
-            match w.status {
-              Status::Begin | Status::Param => {},
-              _ => break,
-            }
+          let request = match requests.get_mut(&request_id) {
+            Some(request) => request,
+            None => break,
+          };
+          match request.status {
+            Status::Begin | Status::Param => {},
+            _ => break,
           }
           match record.data {
             ContentData::Param(data) => {
-              if param_record.len() == 0 {
-                *param_record = data;
+              if request.param.is_empty() {
+                request.param = data;
               } else {
-                for (key, value) in data {
-                  param_record.insert(key, value);
-                }
+                request.param.extend(data);
               } 
-              {
-                let mut w = Mutex::lock(&worker).unwrap();
-                w.status = Status::Param;
-              }
-            },
-            ContentData::None => {
-              let mut w = Mutex::lock(&worker).unwrap();
-              w.status = Status::ParamEnd;
+              request.status = Status::Param;
             },
+            ContentData::None => request.status = Status::ParamEnd,
             _ => break, 
           }
+          Worker::set_status(&worker, Status::Param);
         },
         HeaderType::Stdin => {
           // Got "Stdin" record
-          {
-            let w = Mutex::lock(&worker).unwrap();
-            match w.status {
-              Status::Begin | Status::ParamEnd | Status::Stdin => {},
-              _ => break,
-            }
+          let request = match requests.get_mut(&request_id) {
+            Some(request) => request,
+            None => break,
+          };
+          match request.status {
+            Status::Begin | Status::ParamEnd | Status::Stdin => {},
+            _ => break,
           }
           match record.data {
             ContentData::Stream(data) => {
-              match stdin_record {
-                Some(param) => param.extend_from_slice(&data[..]),
-                None => *stdin_record = Some(data),
+              match &mut request.stdin {
+                Some(stdin) => stdin.extend_from_slice(&data[..]),
+                None => request.stdin = Some(data),
               } 
-              {
-                let mut w = Mutex::lock(&worker).unwrap();
-                w.status = Status::Stdin;
-              }
+              request.status = Status::Stdin;
+              Worker::set_status(&worker, Status::Stdin);
             },
             ContentData::None => {
-              // Got empty "Stdin" record, so we start the CRM
-              {
-                let mut w = Mutex::lock(&worker).unwrap();
-                w.status = Status::Work;
-              }
-              // Start CRM
-              let answer = Sys::run(
-                Arc::clone(&worker), 
-                Rc::clone(&sql), 
-                param_record, 
-                stdin_record, 
-                i18n, 
-                langs, 
-                tpls, 
-              );
-              {
-                let mut w = Mutex::lock(&worker).unwrap();
-                w.count += 1;
-                w.status = Status::End;  
+              // The FastCGI Filter waits for the "Data" stream after the "Stdin" stream
+              if let Role::Filter = FastCGI::role(&request.begin) {
+                request.status = Status::Data;
+                Worker::set_status(&worker, Status::Data);
+                continue;
               }
-              // Write ansewer to the WEB server
-              if let Some(record) = begin_record {
-                FastCGI::write_response(&record.header, answer, &mut stream).unwrap_or(());
-              }
-              break;
+              // Got empty "Stdin" record, so we start the CRM
+              ready = true;
             },
             _ => break,
           }
         },
+        HeaderType::Data => {
+          // Got "Data" record
+          let request = match requests.get_mut(&request_id) {
+            Some(request) => request,
+            None => break,
+          };
+          if Status::Data != request.status {
+            break;
+          }
+          match record.data {
+            ContentData::Stream(data) => {
+              match &mut request.data {
+                Some(d) => d.extend_from_slice(&data[..]),
+                None => request.data = Some(data),
+              } 
+            },
+            // Got empty "Data" record, so we start the CRM
+            ContentData::None => ready = true,
+            _ => break,
+          }
+        },
         _ => {},
       };
+      if !ready {
+        continue;
+      }
+      let request = requests.remove(&request_id).unwrap();
+      let job = Job {
+        begin: request.begin,
+        param: request.param,
+        stdin: request.stdin,
+        data: request.data,
+        writer: Arc::clone(&writer),
+        pending: Arc::clone(&pending),
+        close: !keep_conn,
+      };
+      // Other requests are in progress on this connection, so we hand over this one to a free worker
+      if mpx {
+        let go;
+        let idle;
+        {
+          let w = Mutex::lock(&worker).unwrap();
+          go = Arc::clone(&w.go);
+          idle = Arc::clone(&w.idle);
+        }
+        match idle.try_take() {
+          Some(index) => {
+            Go::send(go, index, Message::Request(job));
+            continue;
+          },
+          None => Worker::run_job(Arc::clone(&worker), job, snapshot),
+        }
+      } else {
+        Worker::run_job(Arc::clone(&worker), job, snapshot);
+      }
+      if !keep_conn && requests.is_empty() {
+        break;
+      }
+      // Wait for the next request on the same connection
+      Worker::set_status(&worker, Status::None);
     }
   }
 }
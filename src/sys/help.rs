@@ -8,12 +8,32 @@ impl Help {
     let desc = "TryTeex is a high-speed FastCGI server for WEB applications written in the RUST programming language.";
     let ver = format!("tryteex version: {}", env!("CARGO_PKG_VERSION"));
     let help = "
-Usage: tryteex [start|stop|help]
+Usage: tryteex [options] [start|stop|restart|reload|status|workers|stats|queue|check-config|help]
 
 Actions:
-    start         : start tryteex server
+    start         : start tryteex server in the background, it writes the pid file and the log
     stop          : stop tryteex server without kill working threads
+    restart       : start a new tryteex server with the same sockets and stop the running one
+    reload        : reload the config file, templates and translations without restart
+    status        : show pid, uptime, number of workers and length of the queue
+    workers       : show status and number of requests of each worker
+    stats         : show totals of connections and requests since the start
+    queue         : show length and capacity of the queue of connections
+    check-config  : check the config file and the templates, with --db test the connection to the database
     help          : this help
+
+Options:
+    --config path : config file, by default tryteex.conf in the current directory
+    --dir path    : root directory for translation files and html templates
+    --socket list : sockets to listen to the WEB server
+    --workers n   : maximum number of workers
+    Any value of the config file can be set by the environment variable, for example TRYTEEX_MAX_CONNECTION=10.
+    The options override the environment variables, they override the config file.
+
+Signals:
+    TERM, INT     : stop tryteex server without kill working threads
+    HUP           : the same as reload
+    USR1          : reopen the log file after the rotation
 ";
     println!("");
     println!("{}", desc);
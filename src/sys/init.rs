@@ -1,8 +1,8 @@
-use std::{process, net::SocketAddr, env::{self, Args}, str::FromStr, fs::read_to_string};
+use std::{process, fmt, net::SocketAddr, env, path::Path, str::FromStr, fs::read_to_string};
 
 use ini_core::{Parser, Item};
 
-use super::log::LogApp;
+use super::error::TryteexError;
 
 // Database connection
 pub struct DB {
@@ -11,13 +11,46 @@ pub struct DB {
   pub user: String,                // Database user
   pub pwd: String,                 // Database user password
   pub name: String,                // Database name
+  pub pool_size: u16,              // Max number of connections to the database, 0 - the same as "max_connection"
+  pub pool_wait: u16,              // Time to wait for a free connection, sec, 0 - a quarter of "handler_timeout"
+  pub pool_idle: u16,              // Idle time after which the free connection is closed, sec
+}
+
+// Socket to listen to
+pub enum Bind {
+  Tcp(SocketAddr),                    // TCP socket. Example: 127.0.0.1:9010
+  Unix(String),                       // Unix domain socket. Example: unix:/run/tryteex.sock
+}
+
+impl fmt::Display for Bind {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Bind::Tcp(addr) => write!(f, "{}", addr),
+      Bind::Unix(path) => write!(f, "unix:{}", path),
+    }
+  }
 }
 
 // Process management
 pub struct Sys {
-  pub max_connection: u16,            // Maximum number of connections
-  pub socket: Vec<SocketAddr>,        // List of sockets to listen to
-  pub irc: SocketAddr,                // IRC socket for server management
+  pub max_connection: u16,            // Maximum number of connections (maximum number of workers)
+  pub min_workers: u16,               // Number of workers which are always running, 0 - all workers
+  pub idle_timeout: u16,              // Idle time after which the extra worker is stopped, sec
+  pub socket: Vec<Bind>,              // List of sockets to listen to
+  pub socket_mode: Option<u32>,       // File mode of the unix domain socket
+  pub socket_owner: String,           // Owner of the unix domain socket. Example: www-data:www-data
+  pub irc: Bind,                      // IRC socket for server management. Example: 127.0.0.1:9100, unix:/run/tryteex.irc
+  pub irc_mode: Option<u32>,          // File mode of the IRC unix domain socket
+  pub irc_owner: String,              // Owner of the IRC unix domain socket. Example: root:adm
+  pub irc_key: String,                // Secret key to sign IRC commands, empty - "salt" is used
+  pub keep_alive: u16,                // Idle timeout of the keep-alive connection from the WEB server, sec
+  pub read_timeout: u16,              // Time to receive the whole request from the WEB server, sec
+  pub handler_timeout: u16,           // Time to run the controller, sec
+  pub write_timeout: u16,             // Time to write the answer to the WEB server, sec
+  pub grace_period: u16,              // Time to finish the requests in progress at the stop, sec
+  pub error_page: String,             // Page for the failed request (HTTP 500), empty - the built-in page
+  pub watch: u16,                     // Interval to check the files of templates and translations and reload them, sec, 0 - off
+  pub pid_file: String,               // File with PID of the running server, empty - no file
 }
 
 // Program action
@@ -25,6 +58,13 @@ pub enum AppAction {
   Start,                          // Start the server in the background stream
   Go,                             // Start the server
   Stop,                           // Stop the server
+  Restart,                        // Restart the server without closing the sockets
+  Reload,                         // Reload the config file, templates and translations
+  Status,                         // Show the summary of the server
+  Workers,                        // Show the state of the workers
+  Stats,                          // Show the totals of the server
+  Queue,                          // Show the state of the queue
+  CheckConfig,                    // Check the config file and the application directory
   Help,                           // Display help information
 }
 
@@ -33,6 +73,10 @@ pub struct Init {
   pub id: u32,                        // Operating system process ID
   pub exe: String,                    // Current program path
   pub dir: String,                    // Current startup directory
+  pub conf: String,                   // Path to the config file
+  pub flags: Vec<String>,             // Options of the command line, they are passed to the started server
+  options: Vec<(&'static str, &'static str, String)>, // Options of the command line, keys and values, they override the config file
+  pub check_db: bool,                 // Test the connection to the database at "check-config"
   pub sys: Sys,                       // Process management
   pub version: String,                // Version
   pub db: DB,                         // Database connection
@@ -44,16 +88,29 @@ pub struct Init {
 impl Init {
 
   // Constructor
-  pub fn new() -> Result<Init, String> {
-    let dir = env::current_dir().unwrap().to_str().unwrap().to_owned();
+  pub fn new() -> Result<Init, TryteexError> {
+    let dir = Init::slash(env::current_dir().unwrap().to_str().unwrap());
     let exe = env::current_exe().unwrap().to_str().unwrap().to_owned();
-    let exe = exe.split(&dir).last().unwrap();
-    let exe = format!("{}{}", dir, exe);
 
     let sys = Sys {
       max_connection: 25,
-      socket: vec![SocketAddr::from_str("127.0.0.1:9001").unwrap()],
-      irc: SocketAddr::from_str("127.0.0.1:9001").unwrap(),
+      min_workers: 0,
+      idle_timeout: 60,
+      socket: vec![Bind::Tcp(SocketAddr::from_str("127.0.0.1:9001").unwrap())],
+      socket_mode: None,
+      socket_owner: "".to_owned(),
+      irc: Bind::Tcp(SocketAddr::from_str("127.0.0.1:9001").unwrap()),
+      irc_mode: None,
+      irc_owner: "".to_owned(),
+      irc_key: "".to_owned(),
+      keep_alive: 60,
+      read_timeout: 60,
+      handler_timeout: 60,
+      write_timeout: 60,
+      grace_period: 30,
+      error_page: "".to_owned(),
+      watch: 0,
+      pid_file: "tryteex.pid".to_owned(),
     };
 
     let db = DB { 
@@ -62,12 +119,19 @@ impl Init {
       user: String::from("user"), 
       pwd: String::from("pwd"), 
       name: String::from("name"),
+      pool_size: 0,
+      pool_wait: 0,
+      pool_idle: 300,
     };
     
     Ok(Init { 
       id: process::id(),
       exe,
+      conf: format!("{}tryteex.conf", dir),
       dir,
+      flags: Vec::new(),
+      options: Vec::new(),
+      check_db: false,
       sys,
       version: env!("CARGO_PKG_VERSION").to_owned(),
       db,
@@ -78,114 +142,595 @@ impl Init {
   }
 
   // Loading the configuration file
-  pub fn load(&mut self) -> Result<(), String> {
-    // Get config file name
-    let file_name = format!("{}/tryteex.conf", self.dir);
+  pub fn load(&mut self) -> Result<(), TryteexError> {
     // Read data
-    let conf = match read_to_string(&file_name) {
+    let conf = match read_to_string(&self.conf) {
       Ok(conf) => conf,
-      Err(err) => return Err(LogApp::get_error(100, &err.to_string())),
+      Err(err) => return Err(TryteexError::Io(100, self.conf.clone(), err)),
     };
-    for item in Parser::new(&conf).auto_trim(true).enumerate() {
-      match item.1 {
-        Item::Property(key, value) => match key {
-          "max_connection" => match value.parse::<u16>() {
-            Ok(val) => match val {
-              0 => return Err(LogApp::get_error(101, value)),
-              _ => self.sys.max_connection = val,
-            },
-            Err(_) => return Err(LogApp::get_error(102, value)),
-          },
-          "socket" => {
-            let mut val: Vec<SocketAddr> = Vec::new();
-            for v in value.split(",") {
-              match SocketAddr::from_str(v) {
-                Ok(s) => val.push(s),
-                Err(_) => return Err(LogApp::get_error(104, v)),
-              }
-            }
-            if self.sys.socket.len() == 0 {
-              return Err(LogApp::get_error(103, ""));
-            }
-            self.sys.socket = val;
-          },
-          "irc" => match value.parse::<u16>() {
-            Ok(val) => match val {
-              0 =>return Err(LogApp::get_error(105, value)),
-              _ => self.sys.irc = SocketAddr::from_str(&format!("127.0.0.1:{}", value)).unwrap(),
-            },
-            Err(_) => return Err(LogApp::get_error(106, value)),
-          },
-          "dir" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(107, value)),
-              1024.. => return Err(LogApp::get_error(108, value)),
-              _ => self.dir = value.to_owned(),
-            }
-          },
-          "version" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(109, value)),
-              12.. => return Err(LogApp::get_error(110, value)),
-              _ => self.version = value.to_owned(),
-            }
-          },
-          "db_host" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(111, value)),
-              _ => self.db.host = value.to_owned(),
-            }
-          },
-          "db_port" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(112, value)),
-              _ => self.db.port = value.to_owned(),
-            }
-          },
-          "db_user" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(113, value)),
-              _ => self.db.user = value.to_owned(),
-            }
-          },
-          "db_pwd" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(114, value)),
-              _ => self.db.pwd = value.to_owned(),
-            }
-          },
-          "db_name" => {
-            match value.trim().len() {
-              0 => return Err(LogApp::get_error(115, value)),
-              _ => self.db.name = value.to_owned(),
-            }
-          },
-          "time_zone" => self.time_zone = value.trim().to_owned(),
-          "salt" => self.salt = value.trim().to_owned(),
-         _ => {},
+    for item in Parser::new(&conf).auto_trim(true) {
+      if let Item::Property(key, value) = item {
+        self.set(key, value)?;
+      }
+    }
+    // Environment variables override the config file. Example: TRYTEEX_MAX_CONNECTION=10
+    for (name, value) in env::vars() {
+      if let Some(key) = Init::env_key(&name) {
+        self.set(&key, value.trim()).map_err(|e| e.wrap(146, &name))?;
+      }
+    }
+    // Options of the command line override both
+    for (name, key, value) in self.options.clone() {
+      self.set(key, &value).map_err(|e| e.wrap(147, &format!("--{}", name)))?;
+    }
+    self.finish()
+  }
+
+  // Check the whole config and set the default values, which depend on other values
+  fn finish(&mut self) -> Result<(), TryteexError> {
+    if self.salt.is_empty() {
+      return Err(TryteexError::Config(116, "".to_owned()));
+    }
+    // By default IRC commands are signed with the salt
+    if self.sys.irc_key.is_empty() {
+      self.sys.irc_key = self.salt.clone();
+    }
+    // By default each worker can have its own connection to the database
+    if self.db.pool_size == 0 {
+      self.db.pool_size = self.sys.max_connection;
+    }
+    // The request must get 503 while the pool is busy, before the watchdog answers 504
+    if self.db.pool_wait >= self.sys.handler_timeout {
+      return Err(TryteexError::Config(150, "".to_owned()));
+    }
+    // By default all workers are started at once
+    if self.sys.min_workers == 0 {
+      self.sys.min_workers = self.sys.max_connection;
+    } else if self.sys.min_workers > self.sys.max_connection {
+      return Err(TryteexError::Config(124, "".to_owned()));
+    }
+    // The PID file is next to the config file by default
+    if !self.sys.pid_file.is_empty() && Path::new(&self.sys.pid_file).is_relative() {
+      if let Some(dir) = Path::new(&self.conf).parent() {
+        self.sys.pid_file = dir.join(&self.sys.pid_file).to_string_lossy().into_owned();
+      }
+    }
+    Ok(())
+  }
+
+  // Check the config file, the environment variables and the options
+  // Unlike "load" all problems are collected. Returns the errors and the warnings
+  pub fn check(&mut self) -> (Vec<String>, Vec<String>) {
+    let mut errors = Vec::new();
+    let mut warnings = Vec::new();
+    let conf = match read_to_string(&self.conf) {
+      Ok(conf) => conf,
+      Err(err) => {
+        errors.push(TryteexError::Io(100, self.conf.clone(), err).to_string());
+        return (errors, warnings);
+      },
+    };
+    for (index, text) in conf.lines().enumerate() {
+      let line = index + 1;
+      match Parser::new(text).auto_trim(true).next() {
+        Some(Item::Property(key, value)) => match self.set(key, value) {
+          Ok(true) => {},
+          Ok(false) => warnings.push(format!("line {}: {}", line, TryteexError::Config(140, key.to_owned()).warning())),
+          Err(e) => errors.push(format!("line {}: {}", line, e)),
         },
+        Some(Item::Error(text)) | Some(Item::Action(text)) => errors.push(format!("line {}: {}", line, TryteexError::Config(141, text.to_owned()))),
+        Some(Item::Section(text)) => warnings.push(format!("line {}: {}", line, TryteexError::Config(142, text.to_owned()).warning())),
         _ => {},
       }
     }
-    if self.salt.len() == 0 {
-      return Err(LogApp::get_error(116, ""));
+    for (name, value) in env::vars() {
+      if let Some(key) = Init::env_key(&name) {
+        match self.set(&key, value.trim()) {
+          Ok(true) => {},
+          Ok(false) => warnings.push(format!("{}: {}", name, TryteexError::Config(140, key).warning())),
+          Err(e) => errors.push(format!("{}: {}", name, e)),
+        }
+      }
+    }
+    for (name, key, value) in self.options.clone() {
+      if let Err(e) = self.set(key, &value) {
+        errors.push(format!("--{}: {}", name, e));
+      }
+    }
+    if let Err(e) = self.finish() {
+      errors.push(e.to_string());
+    }
+    (errors, warnings)
+  }
+
+  // Set one value of the config file, false - the key is unknown
+  pub fn set(&mut self, key: &str, value: &str) -> Result<bool, TryteexError> {
+    match key {
+      "max_connection" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(101, "".to_owned())),
+          _ => self.sys.max_connection = val,
+        },
+        Err(_) => return Err(TryteexError::Config(102, value.to_owned())),
+      },
+      "max_workers" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(122, "".to_owned())),
+          _ => self.sys.max_connection = val,
+        },
+        Err(_) => return Err(TryteexError::Config(123, value.to_owned())),
+      },
+      "min_workers" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(120, "".to_owned())),
+          _ => self.sys.min_workers = val,
+        },
+        Err(_) => return Err(TryteexError::Config(121, value.to_owned())),
+      },
+      "idle_timeout" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(125, "".to_owned())),
+          _ => self.sys.idle_timeout = val,
+        },
+        Err(_) => return Err(TryteexError::Config(126, value.to_owned())),
+      },
+      "grace_period" => match value.parse::<u16>() {
+        Ok(val) => self.sys.grace_period = val,
+        Err(_) => return Err(TryteexError::Config(136, value.to_owned())),
+      },
+      "watch" => match value.parse::<u16>() {
+        Ok(val) => self.sys.watch = val,
+        Err(_) => return Err(TryteexError::Config(137, value.to_owned())),
+      },
+      "pid_file" => self.sys.pid_file = value.trim().to_owned(),
+      "error_page" => match read_to_string(value) {
+        Ok(page) => self.sys.error_page = page,
+        Err(e) => return Err(TryteexError::Io(133, value.to_owned(), e)),
+      },
+      "socket" => {
+        let mut val: Vec<Bind> = Vec::new();
+        for v in value.split(",") {
+          let v = v.trim();
+          if v.is_empty() {
+            continue;
+          }
+          if let Some(path) = v.strip_prefix("unix:") {
+            match path.trim().len() {
+              0 => return Err(TryteexError::Config(103, v.to_owned())),
+              _ => val.push(Bind::Unix(path.trim().to_owned())),
+            }
+            continue;
+          }
+          match SocketAddr::from_str(v) {
+            Ok(s) => val.push(Bind::Tcp(s)),
+            Err(_) => return Err(TryteexError::Config(103, v.to_owned())),
+          }
+        }
+        if val.is_empty() {
+          return Err(TryteexError::Config(104, "".to_owned()));
+        }
+        self.sys.socket = val;
+      },
+      "socket_mode" => match u32::from_str_radix(value, 8) {
+        Ok(val) => match val {
+          0..=0o777 => self.sys.socket_mode = Some(val),
+          _ => return Err(TryteexError::Config(119, value.to_owned())),
+        },
+        Err(_) => return Err(TryteexError::Config(119, value.to_owned())),
+      },
+      "socket_owner" => self.sys.socket_owner = value.trim().to_owned(),
+      "irc" => match value.strip_prefix("unix:") {
+        Some(path) => match path.trim().len() {
+          0 => return Err(TryteexError::Config(138, value.to_owned())),
+          _ => self.sys.irc = Bind::Unix(path.trim().to_owned()),
+        },
+        None => match value.parse::<u16>() {
+          Ok(val) => match val {
+            0 =>return Err(TryteexError::Config(105, "".to_owned())),
+            _ => self.sys.irc = Bind::Tcp(SocketAddr::from_str(&format!("127.0.0.1:{}", value)).unwrap()),
+          },
+          Err(_) => return Err(TryteexError::Config(106, value.to_owned())),
+        },
+      },
+      "irc_mode" => match u32::from_str_radix(value, 8) {
+        Ok(val) => match val {
+          0..=0o777 => self.sys.irc_mode = Some(val),
+          _ => return Err(TryteexError::Config(139, value.to_owned())),
+        },
+        Err(_) => return Err(TryteexError::Config(139, value.to_owned())),
+      },
+      "irc_owner" => self.sys.irc_owner = value.trim().to_owned(),
+      "irc_key" => self.sys.irc_key = value.trim().to_owned(),
+      "keep_alive" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(117, "".to_owned())),
+          _ => self.sys.keep_alive = val,
+        },
+        Err(_) => return Err(TryteexError::Config(118, value.to_owned())),
+      },
+      "read_timeout" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(127, "".to_owned())),
+          _ => self.sys.read_timeout = val,
+        },
+        Err(_) => return Err(TryteexError::Config(128, value.to_owned())),
+      },
+      "handler_timeout" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(129, "".to_owned())),
+          _ => self.sys.handler_timeout = val,
+        },
+        Err(_) => return Err(TryteexError::Config(130, value.to_owned())),
+      },
+      "write_timeout" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(131, "".to_owned())),
+          _ => self.sys.write_timeout = val,
+        },
+        Err(_) => return Err(TryteexError::Config(132, value.to_owned())),
+      },
+      "dir" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(107, value.to_owned())),
+          1024.. => return Err(TryteexError::Config(108, value.to_owned())),
+          _ => self.dir = Init::slash(value),
+        }
+      },
+      "version" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(109, value.to_owned())),
+          12.. => return Err(TryteexError::Config(110, value.to_owned())),
+          _ => self.version = value.to_owned(),
+        }
+      },
+      "db_host" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(111, value.to_owned())),
+          _ => self.db.host = value.to_owned(),
+        }
+      },
+      "db_port" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(112, value.to_owned())),
+          _ => self.db.port = value.to_owned(),
+        }
+      },
+      "db_user" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(113, value.to_owned())),
+          _ => self.db.user = value.to_owned(),
+        }
+      },
+      "db_pwd" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(114, value.to_owned())),
+          _ => self.db.pwd = value.to_owned(),
+        }
+      },
+      "db_name" => {
+        match value.trim().len() {
+          0 => return Err(TryteexError::Config(115, value.to_owned())),
+          _ => self.db.name = value.to_owned(),
+        }
+      },
+      "db_pool_size" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(134, "".to_owned())),
+          _ => self.db.pool_size = val,
+        },
+        Err(_) => return Err(TryteexError::Config(135, value.to_owned())),
+      },
+      "db_pool_wait" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(148, "".to_owned())),
+          _ => self.db.pool_wait = val,
+        },
+        Err(_) => return Err(TryteexError::Config(149, value.to_owned())),
+      },
+      "db_pool_idle" => match value.parse::<u16>() {
+        Ok(val) => match val {
+          0 => return Err(TryteexError::Config(151, "".to_owned())),
+          _ => self.db.pool_idle = val,
+        },
+        Err(_) => return Err(TryteexError::Config(152, value.to_owned())),
+      },
+      "time_zone" => self.time_zone = value.trim().to_owned(),
+      "salt" => self.salt = value.trim().to_owned(),
+      _ => return Ok(false),
+    }
+    Ok(true)
+  }
+
+  // Key of the config file from the name of the environment variable, None - it doesn't override the config file
+  // The server passes its own variables to the started server with "TRYTEEX__". Example: TRYTEEX__DAEMON
+  fn env_key(name: &str) -> Option<String> {
+    match name.strip_prefix("TRYTEEX_") {
+      Some(key) if !key.starts_with('_') => Some(key.to_lowercase()),
+      _ => None,
+    }
+  }
+
+  // The directory with "/" at the end, the paths of the templates and the translations are joined to it without a separator
+  fn slash(dir: &str) -> String {
+    match dir.ends_with('/') {
+      true => dir.to_owned(),
+      false => format!("{}/", dir),
     }
+  }
+
+  // Reload the config file of the running server
+  // The timeouts, the error page and the keys are applied to the next requests and workers, other values need the restart
+  pub fn reload(&mut self) -> Result<(), TryteexError> {
+    let mut init = Init::new()?;
+    init.args(self.flags.clone().into_iter())?;
+    init.load()?;
+    self.sys.idle_timeout = init.sys.idle_timeout;
+    self.sys.keep_alive = init.sys.keep_alive;
+    self.sys.read_timeout = init.sys.read_timeout;
+    self.sys.handler_timeout = init.sys.handler_timeout;
+    self.sys.write_timeout = init.sys.write_timeout;
+    self.sys.grace_period = init.sys.grace_period;
+    self.sys.error_page = init.sys.error_page;
+    self.sys.irc_key = init.sys.irc_key;
+    self.salt = init.salt;
     Ok(())
   }
 
   // Reading program parameters
-  pub fn args(&mut self, args: &mut Args) -> Result<(), String> {
-    args.next();
-    self.app = match args.next() {
-      None => return Ok(()),
-      Some(arg) => match arg.as_str() {
-        "start" => AppAction::Start,
-        "go" => AppAction::Go,
-        "stop" => AppAction::Stop,
-        "help" => AppAction::Help,
-        _ => return Err(LogApp::get_error(200, &arg)),
-      },
-    };
+  // Options can be before or after the command. Example: tryteex --config /etc/tryteex.conf --workers=8 go
+  pub fn args<I: Iterator<Item = String>>(&mut self, mut args: I) -> Result<(), TryteexError> {
+    let mut app = None;
+    while let Some(arg) = args.next() {
+      let flag = match arg.strip_prefix("--") {
+        Some(flag) => flag,
+        None => match app {
+          None => {
+            app = Some(match arg.as_str() {
+              "start" => AppAction::Start,
+              "go" => AppAction::Go,
+              "stop" => AppAction::Stop,
+              "restart" => AppAction::Restart,
+              "reload" => AppAction::Reload,
+              "status" => AppAction::Status,
+              "workers" => AppAction::Workers,
+              "stats" => AppAction::Stats,
+              "queue" => AppAction::Queue,
+              "check-config" => AppAction::CheckConfig,
+              "help" => AppAction::Help,
+              _ => return Err(TryteexError::Config(200, arg.clone())),
+            });
+            continue;
+          },
+          Some(_) => return Err(TryteexError::Config(200, arg.clone())),
+        },
+      };
+      // Test the connection to the database at "check-config"
+      if flag == "db" {
+        self.check_db = true;
+        continue;
+      }
+      let (name, value) = match flag.split_once('=') {
+        Some((name, value)) => (name, Some(value.to_owned())),
+        None => (flag, None),
+      };
+      let (name, key) = match name {
+        "config" => ("config", "config"),
+        "dir" => ("dir", "dir"),
+        "socket" => ("socket", "socket"),
+        "workers" => ("workers", "max_workers"),
+        _ => return Err(TryteexError::Config(204, arg.clone())),
+      };
+      let value = match value.or_else(|| args.next()) {
+        Some(value) => value,
+        None => return Err(TryteexError::Config(205, arg.clone())),
+      };
+      if key == "config" {
+        // The config file is read from the same place after the restart, so the path is absolute
+        let conf = Path::new(&self.dir).join(&value);
+        self.conf = conf.to_string_lossy().into_owned();
+        // The log file and the application are next to the config file by default
+        if let Some(dir) = conf.parent() {
+          self.dir = Init::slash(&dir.to_string_lossy());
+        }
+        self.flags.push(format!("--config={}", self.conf));
+        continue;
+      }
+      self.options.push((name, key, value.clone()));
+      self.flags.push(format!("--{}={}", name, value));
+    }
+    if let Some(app) = app {
+      self.app = app;
+    }
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{env, fs, sync::MutexGuard};
+
+  use tempfile::TempDir;
+
+  use crate::sys::test;
+
+  use super::*;
+
+  // Lock the environment and remove the overrides
+  fn lock() -> MutexGuard<'static, ()> {
+    let env = test::env();
+    for (name, _) in env::vars() {
+      if name.starts_with("TRYTEEX_") {
+        env::remove_var(name);
+      }
+    }
+    env
+  }
+
+  // Write the config file and read the command line
+  fn init(conf: &str, args: &[&str]) -> (TempDir, Result<Init, TryteexError>) {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("tryteex.conf");
+    fs::write(&path, conf).unwrap();
+    let mut init = Init::new().unwrap();
+    let mut list = vec!["--config".to_owned(), path.to_string_lossy().into_owned()];
+    list.extend(args.iter().map(|arg| arg.to_string()));
+    let init = init.args(list.into_iter()).map(|_| init);
+    (dir, init)
+  }
+
+  // Load the config with the overrides
+  fn load(conf: &str, args: &[&str]) -> Result<Init, TryteexError> {
+    let (_dir, init) = init(conf, args);
+    let mut init = init?;
+    init.load()?;
+    Ok(init)
+  }
+
+  #[test]
+  fn config_file_is_read() {
+    let _env = lock();
+    let init = load("salt=abc\nmax_connection=10\n", &[]).unwrap();
+    assert_eq!(init.sys.max_connection, 10);
+    assert_eq!(init.sys.min_workers, 10);
+    assert_eq!(init.salt, "abc");
+  }
+
+  #[test]
+  fn env_overrides_config_file() {
+    let _env = lock();
+    env::set_var("TRYTEEX_MAX_CONNECTION", " 20 ");
+    let init = load("salt=abc\nmax_connection=10\n", &[]);
+    env::remove_var("TRYTEEX_MAX_CONNECTION");
+    assert_eq!(init.unwrap().sys.max_connection, 20);
+  }
+
+  #[test]
+  fn option_overrides_env_and_config_file() {
+    let _env = lock();
+    env::set_var("TRYTEEX_MAX_CONNECTION", "20");
+    let equal = load("salt=abc\nmax_connection=10\n", &["--workers=30", "go"]);
+    let space = load("salt=abc\nmax_connection=10\n", &["go", "--workers", "40"]);
+    env::remove_var("TRYTEEX_MAX_CONNECTION");
+    let equal = equal.unwrap();
+    assert_eq!(equal.sys.max_connection, 30);
+    assert!(matches!(equal.app, AppAction::Go));
+    assert_eq!(space.unwrap().sys.max_connection, 40);
+  }
+
+  #[test]
+  fn internal_variables_are_skipped() {
+    let _env = lock();
+    env::set_var("TRYTEEX__DAEMON", "1");
+    env::set_var("TRYTEEX__HANDOVER", "irc:tcp:3");
+    let init = load("salt=abc\n", &[]);
+    let (errors, warnings) = check("salt=abc\n", &[]);
+    env::remove_var("TRYTEEX__DAEMON");
+    env::remove_var("TRYTEEX__HANDOVER");
+    assert!(init.is_ok());
+    assert!(errors.is_empty());
+    assert!(warnings.is_empty());
+  }
+
+  #[test]
+  fn wrong_overrides_are_named() {
+    let _env = lock();
+    env::set_var("TRYTEEX_KEEP_ALIVE", "x");
+    let e = load("salt=abc\n", &[]).err().unwrap();
+    env::remove_var("TRYTEEX_KEEP_ALIVE");
+    assert_eq!(e.code(), 146);
+    assert_eq!(e.to_string(), "Error 146: Wrong value of the environment variable TRYTEEX_KEEP_ALIVE. Error 118: Unknown value \"keep_alive=x\" in config file");
+    let e = load("salt=abc\n", &["--workers=0"]).err().unwrap();
+    assert_eq!(e.to_string(), "Error 147: Wrong value of the option --workers. Error 122: Value \"max_workers\" must be > 0 in config file");
+  }
+
+  #[test]
+  fn dir_ends_with_slash() {
+    let _env = lock();
+    let (dir, init) = init("salt=abc\n", &[]);
+    let mut init = init.unwrap();
+    init.load().unwrap();
+    assert_eq!(init.dir, format!("{}/", dir.path().to_string_lossy()));
+    assert_eq!(load("salt=abc\ndir=/srv/app\n", &[]).unwrap().dir, "/srv/app/");
+    assert_eq!(load("salt=abc\ndir=/srv/app/\n", &[]).unwrap().dir, "/srv/app/");
+    assert_eq!(load("salt=abc\n", &["--dir=/srv/www"]).unwrap().dir, "/srv/www/");
+  }
+
+  #[test]
+  fn wrong_command_line() {
+    let _env = lock();
+    let code = |args: &[&str]| init("", args).1.err().unwrap().code();
+    assert_eq!(code(&["jump"]), 200);
+    assert_eq!(code(&["start", "stop"]), 200);
+    assert_eq!(code(&["--colour=red"]), 204);
+    assert_eq!(code(&["go", "--workers"]), 205);
+  }
+
+  #[test]
+  fn options_are_passed_to_started_server() {
+    let _env = lock();
+    let (dir, init) = init("", &["--db", "--socket", "unix:/tmp/a.sock", "start"]);
+    let init = init.unwrap();
+    let conf = dir.path().join("tryteex.conf").to_string_lossy().into_owned();
+    assert_eq!(init.flags, vec![format!("--config={}", conf), "--socket=unix:/tmp/a.sock".to_owned()]);
+    assert_eq!(init.conf, conf);
+    assert_eq!(init.dir, format!("{}/", dir.path().to_string_lossy()));
+    assert!(init.check_db);
+  }
+
+  // Check the config file with the overrides
+  fn check(conf: &str, args: &[&str]) -> (Vec<String>, Vec<String>) {
+    let (_dir, init) = init(conf, args);
+    init.unwrap().check()
+  }
+
+  #[test]
+  fn check_reports_each_line() {
+    let _env = lock();
+    let (errors, warnings) = check("salt=abc\n[main]\ncolour=red\n\n; comment\nkeep_alive=x\nmax_connection=0\ngarbage\n", &[]);
+    assert_eq!(errors, vec![
+      "line 6: Error 118: Unknown value \"keep_alive=x\" in config file",
+      "line 7: Error 101: Value \"max_connection\" must be > 0 in config file",
+      "line 8: Error 141: Unrecognized line in config file: garbage",
+    ]);
+    assert_eq!(warnings, vec![
+      "line 2: Warning 142: Sections aren't used in config file, the values are read as without them: main",
+      "line 3: Warning 140: Unknown key in config file: colour",
+    ]);
+  }
+
+  #[test]
+  fn check_reports_overrides() {
+    let _env = lock();
+    env::set_var("TRYTEEX_READ_TIMEOUT", "x");
+    env::set_var("TRYTEEX_COLOUR", "red");
+    let (errors, warnings) = check("salt=abc\n", &["--workers=0"]);
+    env::remove_var("TRYTEEX_READ_TIMEOUT");
+    env::remove_var("TRYTEEX_COLOUR");
+    assert_eq!(errors, vec![
+      "TRYTEEX_READ_TIMEOUT: Error 128: Unknown value \"read_timeout=x\" in config file",
+      "--workers: Error 122: Value \"max_workers\" must be > 0 in config file",
+    ]);
+    assert_eq!(warnings, vec!["TRYTEEX_COLOUR: Warning 140: Unknown key in config file: colour"]);
+  }
+
+  #[test]
+  fn check_reports_whole_config() {
+    let _env = lock();
+    let (errors, warnings) = check("max_connection=4\nmin_workers=8\n", &[]);
+    assert_eq!(errors, vec!["Error 116: Value \"salt\" mustn't be empty in config file"]);
+    assert!(warnings.is_empty());
+    let (errors, _) = check("salt=abc\nmax_connection=4\nmin_workers=8\n", &[]);
+    assert_eq!(errors, vec!["Error 124: Value \"min_workers\" must be <= \"max_workers\" in config file"]);
+  }
+
+  #[test]
+  fn check_reports_missing_file() {
+    let _env = lock();
+    let mut init = Init::new().unwrap();
+    init.args(vec!["--config".to_owned(), "/nonexistent/tryteex.conf".to_owned()].into_iter()).unwrap();
+    let (errors, warnings) = init.check();
+    assert_eq!(errors.len(), 1);
+    assert!(errors[0].starts_with("Error 100: Unknown error when opening config file: /nonexistent/tryteex.conf "));
+    assert!(warnings.is_empty());
+  }
+}
@@ -0,0 +1,293 @@
+use std::{fs::File, io::{Read, Write, Error, ErrorKind}, process, time::{SystemTime, UNIX_EPOCH}};
+
+use hmac::{Hmac, Mac};
+use serde::{Serialize, Deserialize, de::DeserializeOwned};
+use sha3::{Digest, Sha3_256};
+
+type HmacSha3 = Hmac<Sha3_256>;
+
+// Version of the IRC protocol, the server rejects the requests of the other versions
+pub const IRC_VERSION: u16 = 1;
+// Max size of one IRC message
+pub const IRC_MAX_LEN: usize = 1024 * 1024;
+// Max size of the request, it is read before the signature is checked
+pub const IRC_REQUEST_LEN: usize = 4 * 1024;
+// Time to send the whole request after the connection, sec
+pub const IRC_DEADLINE: u64 = 5;
+
+// Commands of the IRC channel
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum IrcCommand {
+  Stop,                                 // Stop the server
+  Restart,                              // Start a new server with the same sockets and stop this one
+  Reload,                               // Reload the config file, templates and translations
+  Status,                               // Summary of the server
+  Workers,                              // State of the workers
+  Stats,                                // Totals since the start
+  Queue,                                // State of the queue of the connections
+}
+
+// Kind of the error in the IRC answer
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum IrcFault {
+  Format,                               // The request can't be decoded
+  Version,                              // Another version of the protocol
+  Access,                               // Wrong signature
+  Failed,                               // The command is failed
+}
+
+// First message of the server with the random challenge
+#[derive(Serialize, Deserialize)]
+pub struct IrcChallenge {
+  pub version: u16,                     // Version of the protocol
+  pub pid: u32,                         // PID of the server
+  pub nonce: String,                    // Random challenge
+}
+
+// Request of the client
+#[derive(Serialize, Deserialize)]
+pub struct IrcRequest {
+  pub version: u16,                     // Version of the protocol
+  pub pid: u32,                         // PID of the client
+  pub command: IrcCommand,              // Command
+  #[serde(default)]
+  pub param: String,                    // Parameter of the command
+  pub sign: String,                     // Signature of the command for the challenge
+}
+
+// Error in the IRC answer
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IrcError {
+  pub kind: IrcFault,                   // Kind of the error
+  pub text: String,                     // Description
+}
+
+// Answer of the server
+#[derive(Serialize, Deserialize)]
+pub struct IrcAnswer {
+  pub version: u16,                     // Version of the protocol
+  pub pid: u32,                         // PID of the server
+  pub command: Option<IrcCommand>,      // Command, None - the request isn't decoded
+  #[serde(default)]
+  pub data: String,                     // Result of the command
+  pub error: Option<IrcError>,          // Error, None - the command is done
+}
+
+impl IrcCommand {
+  // Name of the command, the same as in JSON. It is signed, so it must not change
+  pub fn name(&self) -> &'static str {
+    match self {
+      IrcCommand::Stop => "stop",
+      IrcCommand::Restart => "restart",
+      IrcCommand::Reload => "reload",
+      IrcCommand::Status => "status",
+      IrcCommand::Workers => "workers",
+      IrcCommand::Stats => "stats",
+      IrcCommand::Queue => "queue",
+    }
+  }
+}
+
+impl IrcRequest {
+  // Create and sign the request
+  pub fn new(pid: u32, command: IrcCommand, param: &str, key: &str, nonce: &str) -> IrcRequest {
+    let mut request = IrcRequest {
+      version: IRC_VERSION,
+      pid,
+      command,
+      param: param.to_owned(),
+      sign: "".to_owned(),
+    };
+    request.sign = Irc::sign(key, nonce, &request.signed());
+    request
+  }
+
+  // Signed part of the request
+  pub fn signed(&self) -> String {
+    format!("{} {} {} {}", self.version, self.pid, self.command.name(), self.param)
+  }
+}
+
+impl IrcAnswer {
+  // The command is done
+  pub fn ok(pid: u32, command: Option<IrcCommand>, data: String) -> IrcAnswer {
+    IrcAnswer { version: IRC_VERSION, pid, command, data, error: None }
+  }
+
+  // The command is failed
+  pub fn error(pid: u32, command: Option<IrcCommand>, kind: IrcFault, text: &str) -> IrcAnswer {
+    IrcAnswer { version: IRC_VERSION, pid, command, data: "".to_owned(), error: Some(IrcError { kind, text: text.to_owned() }) }
+  }
+}
+
+// Protocol of the IRC channel
+// Each message is a JSON object prefixed with its length (4 bytes, big endian).
+// The server sends a random challenge, the client signs it together with the command by the shared key.
+pub struct Irc {}
+
+impl Irc {
+  // Write one message
+  pub fn write<T: Serialize, W: Write>(stream: &mut W, message: &T) -> Result<(), Error> {
+    let data = serde_json::to_vec(message).map_err(|e| Error::new(ErrorKind::InvalidData, e))?;
+    let mut buffer = Vec::with_capacity(data.len() + 4);
+    buffer.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buffer.extend_from_slice(&data);
+    stream.write_all(&buffer)
+  }
+
+  // Read one message, which isn't longer than "max"
+  pub fn read<T: DeserializeOwned, R: Read>(stream: &mut R, max: usize) -> Result<T, Error> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > max {
+      return Err(Error::new(ErrorKind::InvalidData, format!("message is too long: {} bytes", len)));
+    }
+    let mut data = vec![0; len];
+    stream.read_exact(&mut data)?;
+    serde_json::from_slice(&data).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+  }
+
+  // Generate a random challenge
+  pub fn nonce() -> String {
+    let mut hasher = Sha3_256::new();
+    let mut random = [0u8; 32];
+    if let Ok(mut file) = File::open("/dev/urandom") {
+      file.read_exact(&mut random).unwrap_or(());
+    }
+    hasher.update(random);
+    // The time and the PID make the challenge unique, even if the system random isn't available
+    let time = SystemTime::now().duration_since(UNIX_EPOCH).map(|t| t.as_nanos()).unwrap_or(0);
+    hasher.update(format!("{} {}", time, process::id()).as_bytes());
+    format!("{:x}", hasher.finalize())
+  }
+
+  // Sign the command for the challenge
+  pub fn sign(key: &str, nonce: &str, command: &str) -> String {
+    let mac = Irc::mac(key, nonce, command);
+    let mut text = String::with_capacity(64);
+    for byte in mac.finalize().into_bytes() {
+      text.push_str(&format!("{:02x}", byte));
+    }
+    text
+  }
+
+  // Check the signature of the command in constant time
+  pub fn verify(key: &str, nonce: &str, command: &str, sign: &str) -> bool {
+    if sign.len() != 64 || !sign.is_ascii() {
+      return false;
+    }
+    let mut bytes = Vec::with_capacity(32);
+    for i in (0..64).step_by(2) {
+      match u8::from_str_radix(&sign[i..i + 2], 16) {
+        Ok(byte) => bytes.push(byte),
+        Err(_) => return false,
+      }
+    }
+    Irc::mac(key, nonce, command).verify_slice(&bytes).is_ok()
+  }
+
+  // HMAC-SHA3-256 of the challenge and the command
+  fn mac(key: &str, nonce: &str, command: &str) -> HmacSha3 {
+    let mut mac = HmacSha3::new_from_slice(key.as_bytes()).expect("HMAC accepts a key of any size");
+    mac.update(nonce.as_bytes());
+    mac.update(b" ");
+    mac.update(command.as_bytes());
+    mac
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::{Cursor, ErrorKind};
+
+  use super::*;
+
+  #[test]
+  fn request_round_trip() {
+    let request = IrcRequest::new(7, IrcCommand::Workers, "all", "key", "nonce");
+    let mut buffer = Vec::new();
+    Irc::write(&mut buffer, &request).unwrap();
+    assert_eq!(u32::from_be_bytes(buffer[..4].try_into().unwrap()) as usize, buffer.len() - 4);
+    let read: IrcRequest = Irc::read(&mut Cursor::new(buffer), IRC_REQUEST_LEN).unwrap();
+    assert_eq!(read.version, IRC_VERSION);
+    assert_eq!(read.pid, 7);
+    assert_eq!(read.command, IrcCommand::Workers);
+    assert_eq!(read.param, "all");
+    assert_eq!(read.sign, request.sign);
+  }
+
+  #[test]
+  fn answer_round_trip() {
+    let mut buffer = Vec::new();
+    Irc::write(&mut buffer, &IrcAnswer::error(9, Some(IrcCommand::Reload), IrcFault::Failed, "no salt")).unwrap();
+    Irc::write(&mut buffer, &IrcAnswer::ok(9, Some(IrcCommand::Status), "pid: 9\n".to_owned())).unwrap();
+    let mut cursor = Cursor::new(buffer);
+    let error: IrcAnswer = Irc::read(&mut cursor, IRC_MAX_LEN).unwrap();
+    let error = error.error.unwrap();
+    assert_eq!(error.kind, IrcFault::Failed);
+    assert_eq!(error.text, "no salt");
+    let ok: IrcAnswer = Irc::read(&mut cursor, IRC_MAX_LEN).unwrap();
+    assert_eq!(ok.command, Some(IrcCommand::Status));
+    assert_eq!(ok.data, "pid: 9\n");
+    assert!(ok.error.is_none());
+  }
+
+  #[test]
+  fn command_name_is_json_name() {
+    for command in [IrcCommand::Stop, IrcCommand::Restart, IrcCommand::Reload, IrcCommand::Status, IrcCommand::Workers, IrcCommand::Stats, IrcCommand::Queue] {
+      assert_eq!(serde_json::to_string(&command).unwrap(), format!("\"{}\"", command.name()));
+    }
+  }
+
+  #[test]
+  fn signature_is_verified() {
+    let request = IrcRequest::new(1, IrcCommand::Stop, "", "key", "nonce");
+    assert!(Irc::verify("key", "nonce", &request.signed(), &request.sign));
+    // Another key, challenge or command
+    assert!(!Irc::verify("other", "nonce", &request.signed(), &request.sign));
+    assert!(!Irc::verify("key", "other", &request.signed(), &request.sign));
+    let restart = IrcRequest { command: IrcCommand::Restart, ..request };
+    assert!(!Irc::verify("key", "nonce", &restart.signed(), &restart.sign));
+  }
+
+  #[test]
+  fn malformed_signature_is_rejected() {
+    let request = IrcRequest::new(1, IrcCommand::Stop, "", "key", "nonce");
+    assert!(!Irc::verify("key", "nonce", &request.signed(), ""));
+    assert!(!Irc::verify("key", "nonce", &request.signed(), &request.sign[..62]));
+    assert!(!Irc::verify("key", "nonce", &request.signed(), &"zz".repeat(32)));
+    assert!(!Irc::verify("key", "nonce", &request.signed(), &"é".repeat(32)));
+  }
+
+  #[test]
+  fn oversize_message_is_rejected() {
+    let mut buffer = ((IRC_MAX_LEN + 1) as u32).to_be_bytes().to_vec();
+    buffer.extend_from_slice(b"{}");
+    let e = Irc::read::<IrcAnswer, _>(&mut Cursor::new(buffer), IRC_MAX_LEN).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+  }
+
+  #[test]
+  fn long_request_is_rejected_before_reading() {
+    let request = IrcRequest::new(1, IrcCommand::Status, &"x".repeat(IRC_REQUEST_LEN), "key", "nonce");
+    let mut buffer = Vec::new();
+    Irc::write(&mut buffer, &request).unwrap();
+    let mut cursor = Cursor::new(buffer);
+    let e = Irc::read::<IrcRequest, _>(&mut cursor, IRC_REQUEST_LEN).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::InvalidData);
+    // Only the length is read
+    assert_eq!(cursor.position(), 4);
+  }
+
+  #[test]
+  fn truncated_message_is_rejected() {
+    let mut buffer = Vec::new();
+    Irc::write(&mut buffer, &IrcAnswer::ok(1, None, "data".to_owned())).unwrap();
+    buffer.pop();
+    let e = Irc::read::<IrcAnswer, _>(&mut Cursor::new(buffer), IRC_MAX_LEN).err().unwrap();
+    assert_eq!(e.kind(), ErrorKind::UnexpectedEof);
+  }
+}
@@ -0,0 +1,9 @@
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+// The environment variables are shared by all tests of the process, which run in parallel
+static ENV: Mutex<()> = Mutex::new(());
+
+// Lock the environment variables while the test changes them
+pub fn env() -> MutexGuard<'static, ()> {
+  ENV.lock().unwrap_or_else(PoisonError::into_inner)
+}